@@ -1,151 +1,7646 @@
 #![feature(box_syntax, box_patterns, custom_derive)]
+#![allow(dead_code)]
 
 // Step 1: remove implication (A -> B ~> ~A v B), (A <-> B ~> A -> B ^ B -> A)
 // Step 2: use double-negation (~~F ~> F) and de morgan to push negation down to leaves
 // Step 3: Repeatedly use distributive laws ('and other laws'?!?) to obtain a normal form
 
+use std::cmp::Reverse;
 #[allow(unused_imports)]
 use std::fmt::{self, Formatter, Display};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, Write};
+use std::mem;
+use std::ops::ControlFlow;
+use std::ops::Range;
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
 enum Formula {
+    True,
+    False,
     Atom(char),
     Not(Box<Formula>),
     Implies { l: Box<Formula>, r: Box<Formula> },
     Iff { l: Box<Formula>, r: Box<Formula> },
+    // All children have the same truth value.
+    IffAll(Vec<Formula>),
     And(Vec<Formula>),
     Or(Vec<Formula>),
+    // `inner` with a label attached, e.g. the requirement a clause came from.  It means the same as `inner`:
+    // evaluation looks through it, and the simplifiers either pass it through or strip it.
+    Annotated { note: String, inner: Box<Formula> },
 }
 
 impl fmt::Display for Formula {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", print_formula(self))
+        // The alternate form, `{:#}`, also shows annotations.
+        write!(f, "{}", print_formula(self, f.alternate()))
     }
 }
 
-fn print_formula(f : &Formula) -> String {
+fn print_formula(f : &Formula, notes : bool) -> String {
+    let print_formula = |g : &Formula| print_formula(g, notes);
     match f
     {
+        &Formula::True => "TRUE".to_string(),
+        &Formula::False => "FALSE".to_string(),
         &Formula::Atom(ref c) => format!("{}", c),
         &Formula::Not(ref n) => format!("~({})", print_formula(n.as_ref())),
         &Formula::Implies { ref l, ref r } => format!("{} -> {}", print_formula(l.as_ref()), print_formula(r.as_ref())),
         &Formula::Iff { ref l, ref r } => format!("{} <-> {}", print_formula(l.as_ref()), print_formula(r.as_ref())),
+        &Formula::IffAll(ref v) => format!("IFF({})", v.iter().map(|ref x| print_formula(&x)).collect::<Vec<String>>().join(", ")),
         &Formula::And(ref v) => format!("({})", v.iter().map(|ref x| print_formula(&x)).collect::<Vec<String>>().join(" AND ")),
         &Formula::Or(ref v) => format!("({})", v.iter().map(|ref x| print_formula(&x)).collect::<Vec<String>>().join(" OR ")),
+        &Formula::Annotated { ref note, ref inner } if notes => format!("{} [{}]", print_formula(inner), note),
+        &Formula::Annotated { ref inner, .. } => print_formula(inner),
     }
 }
 
-fn simplify(f : Formula) -> Formula
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Parens {
+    // Wrap every operand that is not an atom.
+    Always,
+    // Only where precedence or associativity requires it, or where an AND/OR directly contains another
+    // of the same kind (so the nesting survives a round trip).
+    Minimal,
+}
+
+// The strings used for each connective by `print_with`.  Binary connectives are written with a space on
+// either side; `not` is written directly before its operand.  Other styles start from one of the presets,
+// e.g. `PrintStyle { parens: Parens::Always, ..PrintStyle::ascii() }`.
+#[derive(Clone, PartialEq, Debug)]
+struct PrintStyle
 {
-	let (sf, _) = simplify3(simplify2(simplify1(f)));
-	sf
+    not: String,
+    and: String,
+    or: String,
+    implies: String,
+    iff: String,
+    top: String,
+    bottom: String,
+    parens: Parens,
 }
 
-fn simplify1(f : Formula) -> Formula
+impl PrintStyle
+{
+    // The connectives of `Display` and `parse`.
+    fn ascii() -> PrintStyle
+    {
+        PrintStyle {
+            not: "~".to_string(),
+            and: "AND".to_string(),
+            or: "OR".to_string(),
+            implies: "->".to_string(),
+            iff: "<->".to_string(),
+            top: "TRUE".to_string(),
+            bottom: "FALSE".to_string(),
+            parens: Parens::Minimal,
+        }
+    }
+
+    fn c_style() -> PrintStyle
+    {
+        PrintStyle {
+            not: "!".to_string(),
+            and: "&&".to_string(),
+            or: "||".to_string(),
+            implies: "->".to_string(),
+            iff: "<->".to_string(),
+            top: "true".to_string(),
+            bottom: "false".to_string(),
+            parens: Parens::Minimal,
+        }
+    }
+
+    fn unicode() -> PrintStyle
+    {
+        PrintStyle {
+            not: "¬".to_string(),
+            and: "∧".to_string(),
+            or: "∨".to_string(),
+            implies: "→".to_string(),
+            iff: "↔".to_string(),
+            top: "⊤".to_string(),
+            bottom: "⊥".to_string(),
+            parens: Parens::Minimal,
+        }
+    }
+
+    // For pasting into LaTeX maths mode.  This one has no matching parser.
+    fn latex() -> PrintStyle
+    {
+        PrintStyle {
+            not: "\\lnot ".to_string(),
+            and: "\\land".to_string(),
+            or: "\\lor".to_string(),
+            implies: "\\rightarrow".to_string(),
+            iff: "\\leftrightarrow".to_string(),
+            top: "\\top".to_string(),
+            bottom: "\\bot".to_string(),
+            parens: Parens::Minimal,
+        }
+    }
+}
+
+// Binding strength of each connective, loosest first.
+fn precedence(f : &Formula) -> u8
 {
     match f
     {
-        g @ Formula::Atom(_) => g,
-        Formula::Not(n) => Formula::Not(box simplify1(*n)),
-        Formula::Implies { l, r } => Formula::Or(vec!(Formula::Not(box simplify1(*l)), simplify1(*r))),
-        Formula::Iff { l, r } => {
-            let ls = simplify1(*l);
-            let rs = simplify1(*r);
-            let nl = Formula::Or(vec!(Formula::Not(box ls.clone()), rs.clone()));
-            let nr = Formula::Or(vec!(Formula::Not(box rs), ls));
-            Formula::And(vec!(nl, nr))
+        &Formula::Iff { .. } => 1,
+        &Formula::Implies { .. } => 2,
+        &Formula::Or(_) => 3,
+        &Formula::And(_) => 4,
+        &Formula::Not(_) => 5,
+        &Formula::True | &Formula::False | &Formula::Atom(_) | &Formula::IffAll(_) => 6,
+        &Formula::Annotated { ref inner, .. } => precedence(inner),
+    }
+}
+
+fn print_with(f : &Formula, style : &PrintStyle) -> String
+{
+    // Print `child` as an operand of a connective with precedence `outer`.  `loose_ok` allows a child at
+    // the same precedence without parentheses (the right operand of a right-associative connective).
+    let operand = |child : &Formula, outer : u8, loose_ok : bool| {
+        let p = precedence(child);
+        let wrap = match style.parens
+        {
+            Parens::Always => p < 6,
+            Parens::Minimal => p < outer || (p == outer && !loose_ok),
+        };
+        let s = print_with(child, style);
+        if wrap { format!("({})", s) } else { s }
+    };
+
+    match f
+    {
+        &Formula::True => style.top.clone(),
+        &Formula::False => style.bottom.clone(),
+        &Formula::Atom(ref c) => format!("{}", c),
+        &Formula::Not(ref n) => format!("{}{}", style.not, operand(n, 5, true)),
+        &Formula::Implies { ref l, ref r } => format!("{} {} {}", operand(l, 2, false), style.implies, operand(r, 2, true)),
+        &Formula::Iff { ref l, ref r } => format!("{} {} {}", operand(l, 1, false), style.iff, operand(r, 1, true)),
+        &Formula::IffAll(ref v) => format!("IFF({})", v.iter().map(|x| print_with(x, style)).collect::<Vec<String>>().join(", ")),
+        &Formula::And(ref v) => v.iter().map(|x| operand(x, 4, false)).collect::<Vec<String>>().join(&format!(" {} ", style.and)),
+        &Formula::Or(ref v) => v.iter().map(|x| operand(x, 3, false)).collect::<Vec<String>>().join(&format!(" {} ", style.or)),
+        // None of the styles has a syntax for annotations.
+        &Formula::Annotated { ref inner, .. } => print_with(inner, style),
+    }
+}
+
+// A name for the atom `c` that other tools accept as an identifier: `c` itself if it is an ASCII letter, d
+// followed by it if it is an ASCII digit (identifiers cannot start with one), and otherwise x followed by
+// its code point in hex.  Only letters get one-character names, so distinct atoms get distinct names.
+fn ascii_name(c : char) -> String
+{
+    if c.is_ascii_alphabetic() {
+        c.to_string()
+    } else if c.is_ascii_digit() {
+        format!("d{}", c)
+    } else {
+        format!("x{:x}", c as u32)
+    }
+}
+
+// `f` as an SMT-LIB 2 term, with each atom a Bool constant named by `ascii_name`.  SMT-LIB's `=` is
+// chainable, so IFF(...) is written with it.
+fn print_smtlib(f : &Formula) -> String
+{
+    let app = |op : &str, v : &[Formula], empty : &str| match v.len()
+    {
+        0 => empty.to_string(),
+        1 => print_smtlib(&v[0]),
+        _ => format!("({} {})", op, v.iter().map(print_smtlib).collect::<Vec<String>>().join(" ")),
+    };
+
+    match f
+    {
+        &Formula::True => "true".to_string(),
+        &Formula::False => "false".to_string(),
+        &Formula::Atom(c) => ascii_name(c),
+        &Formula::Not(ref n) => format!("(not {})", print_smtlib(n)),
+        &Formula::Implies { ref l, ref r } => format!("(=> {} {})", print_smtlib(l), print_smtlib(r)),
+        &Formula::Iff { ref l, ref r } => format!("(= {} {})", print_smtlib(l), print_smtlib(r)),
+        &Formula::IffAll(ref v) if v.len() < 2 => "true".to_string(),
+        &Formula::IffAll(ref v) => app("=", v, "true"),
+        &Formula::And(ref v) => app("and", v, "true"),
+        &Formula::Or(ref v) => app("or", v, "false"),
+        &Formula::Annotated { ref inner, .. } => print_smtlib(inner),
+    }
+}
+
+// A complete SMT-LIB 2 script checking `f`, e.g. to run the same problem in another solver: a Bool
+// constant declared per atom, then `f` asserted, `(check-sat)` and `(get-model)`.
+fn to_smtlib_script(f : &Formula) -> String
+{
+    let mut s = String::from("(set-logic QF_UF)\n");
+    for c in atoms(f) {
+        s.push_str(&format!("(declare-fun {} () Bool)\n", ascii_name(c)));
+    }
+    s.push_str(&format!("(assert {})\n(check-sat)\n(get-model)\n", print_smtlib(f)));
+    s
+}
+
+// The immediate subformulas of `f`, left to right.
+fn children(f : &Formula) -> Vec<&Formula>
+{
+    match f
+    {
+        &Formula::True | &Formula::False | &Formula::Atom(_) => vec!(),
+        &Formula::Not(ref n) | &Formula::Annotated { inner: ref n, .. } => vec!(n.as_ref()),
+        &Formula::Implies { ref l, ref r } | &Formula::Iff { ref l, ref r } => vec!(l.as_ref(), r.as_ref()),
+        &Formula::IffAll(ref v) | &Formula::And(ref v) | &Formula::Or(ref v) => v.iter().collect(),
+    }
+}
+
+fn children_mut(f : &mut Formula) -> Vec<&mut Formula>
+{
+    match f
+    {
+        &mut Formula::True | &mut Formula::False | &mut Formula::Atom(_) => vec!(),
+        &mut Formula::Not(ref mut n) | &mut Formula::Annotated { inner: ref mut n, .. } => vec!(n.as_mut()),
+        &mut Formula::Implies { ref mut l, ref mut r } | &mut Formula::Iff { ref mut l, ref mut r } => vec!(l.as_mut(), r.as_mut()),
+        &mut Formula::IffAll(ref mut v) | &mut Formula::And(ref mut v) | &mut Formula::Or(ref mut v) => v.iter_mut().collect(),
+    }
+}
+
+// A bottom-up traversal: `visit` is called on every node after its children, with the node and the
+// results for its children in order, and the result for the root is returned.  Traversals that only care
+// about a few kinds of node can match on those and treat the rest uniformly, e.g. `size` below.
+fn fold<T, F: FnMut(&Formula, Vec<T>) -> T>(f : &Formula, visit : &mut F) -> T
+{
+    let results = children(f).into_iter().map(|c| fold(c, visit)).collect();
+    visit(f, results)
+}
+
+// The number of nodes.
+fn size(f : &Formula) -> usize
+{
+    fold(f, &mut |_, sizes : Vec<usize>| 1 + sizes.iter().sum::<usize>())
+}
+
+// The number of distinct subformulas, i.e. the nodes of `f` once equal subtrees are shared as they would
+// be under hash-consing.  At most `size(f)`.
+fn dag_size(f : &Formula) -> usize
+{
+    let mut seen : HashSet<&Formula> = HashSet::new();
+    let mut stack = vec!(f);
+    while let Some(g) = stack.pop() {
+        if seen.insert(g) {
+            stack.extend(children(g));
+        }
+    }
+    seen.len()
+}
+
+// The number of nodes on the longest path from the root to a leaf, so an atom has depth 1.
+fn depth(f : &Formula) -> usize
+{
+    fold(f, &mut |_, depths : Vec<usize>| 1 + depths.into_iter().max().unwrap_or(0))
+}
+
+// A small formula that `still_fails`, found by shrinking `f` (which should satisfy it) one step at a time
+// for as long as some step keeps the predicate true.  A step replaces one subformula by TRUE, FALSE or one
+// of its atoms, by one of its children, or, for an AND or OR with more than two children, by itself
+// without one of them.  Only steps that reduce the `size` are taken, so the result cannot be shrunk any
+// further by a single step.  Meant for reducing a formula that triggers a bug to one worth reading.
+fn shrink(f : &Formula, still_fails : impl Fn(&Formula) -> bool) -> Formula
+{
+    // The first formula one step away from `g` that `accept` turns into Some, trying them in a fixed order
+    // and building each only when its turn comes, so a step that is taken early costs little.  Steps inside
+    // a child are passed to `accept` already put back into `g`.
+    fn first_step(g : &Formula, accept : &mut dyn FnMut(Formula) -> Option<Formula>) -> Option<Formula>
+    {
+        let simple = vec!(Formula::True, Formula::False).into_iter()
+            .chain(atoms(g).into_iter().map(Formula::Atom))
+            .chain(children(g).into_iter().cloned());
+        for h in simple {
+            if let Some(found) = accept(h) {
+                return Some(found);
+            }
+        }
+        match g
+        {
+            &Formula::And(ref v) | &Formula::Or(ref v) if v.len() > 2 => for i in 0..v.len() {
+                let mut w = v.clone();
+                w.remove(i);
+                if let Some(found) = accept(match g { &Formula::And(_) => Formula::And(w), _ => Formula::Or(w) }) {
+                    return Some(found);
+                }
+            },
+            _ => {}
+        }
+        for (i, child) in children(g).into_iter().enumerate() {
+            let found = first_step(child, &mut |c| {
+                let mut h = g.clone();
+                *children_mut(&mut h).into_iter().nth(i).unwrap() = c;
+                accept(h)
+            });
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
+    }
+
+    let mut f = f.clone();
+    while let Some(g) = first_step(&f, &mut |g| if size(&g) < size(&f) && still_fails(&g) { Some(g) } else { None }) {
+        f = g;
+    }
+    f
+}
+
+// The distinct atoms of a formula, in sorted order.  This is the column order used by the truth table
+// functions.
+fn atoms(f : &Formula) -> Vec<char>
+{
+    fn collect(f : &Formula, set : &mut BTreeSet<char>)
+    {
+        match f
+        {
+            &Formula::True | &Formula::False => {}
+            &Formula::Atom(c) => { set.insert(c); }
+            &Formula::Not(ref n) | &Formula::Annotated { inner: ref n, .. } => collect(n, set),
+            &Formula::Implies { ref l, ref r } | &Formula::Iff { ref l, ref r } => { collect(l, set); collect(r, set); }
+            &Formula::IffAll(ref v) | &Formula::And(ref v) | &Formula::Or(ref v) => for x in v { collect(x, set); },
+        }
+    }
+
+    let mut set = BTreeSet::new();
+    collect(f, &mut set);
+    set.into_iter().collect()
+}
+
+// Evaluate a formula under an assignment.  Panics if an atom of the formula is not assigned.
+fn eval(f : &Formula, env : &HashMap<char, bool>) -> bool
+{
+    match f
+    {
+        &Formula::True => true,
+        &Formula::False => false,
+        &Formula::Atom(ref c) => env[c],
+        &Formula::Not(ref n) => !eval(n, env),
+        &Formula::Implies { ref l, ref r } => !eval(l, env) || eval(r, env),
+        &Formula::Iff { ref l, ref r } => eval(l, env) == eval(r, env),
+        &Formula::IffAll(ref v) => v.iter().all(|x| eval(x, env)) || !v.iter().any(|x| eval(x, env)),
+        &Formula::And(ref v) => v.iter().all(|x| eval(x, env)),
+        &Formula::Or(ref v) => v.iter().any(|x| eval(x, env)),
+        &Formula::Annotated { ref inner, .. } => eval(inner, env),
+    }
+}
+
+// The operations `eval_in` needs from the values it evaluates into.  Besides `bool`, a `u64` is handled
+// bitwise, i.e. as 64 independent truth values, so one evaluation covers 64 assignments.
+trait BooleanAlgebra : Clone
+{
+    fn top() -> Self;
+    fn bottom() -> Self;
+    fn and(&self, other : &Self) -> Self;
+    fn or(&self, other : &Self) -> Self;
+    fn not(&self) -> Self;
+}
+
+impl BooleanAlgebra for bool
+{
+    fn top() -> bool
+    {
+        true
+    }
+
+    fn bottom() -> bool
+    {
+        false
+    }
+
+    fn and(&self, other : &bool) -> bool
+    {
+        *self && *other
+    }
+
+    fn or(&self, other : &bool) -> bool
+    {
+        *self || *other
+    }
+
+    fn not(&self) -> bool
+    {
+        !*self
+    }
+}
+
+impl BooleanAlgebra for u64
+{
+    fn top() -> u64
+    {
+        !0
+    }
+
+    fn bottom() -> u64
+    {
+        0
+    }
+
+    fn and(&self, other : &u64) -> u64
+    {
+        self & other
+    }
+
+    fn or(&self, other : &u64) -> u64
+    {
+        self | other
+    }
+
+    fn not(&self) -> u64
+    {
+        !self
+    }
+}
+
+// `eval` with the atoms taking values in any `BooleanAlgebra`.  The other connectives are expressed with
+// AND, OR and NOT: l -> r as ~l v r, l <-> r as (l ^ r) v (~l ^ ~r) and IFF(...) as all true or all false.
+fn eval_in<B : BooleanAlgebra>(f : &Formula, env : &HashMap<char, B>) -> B
+{
+    let all = |v : &[B]| v.iter().fold(B::top(), |acc, x| acc.and(x));
+    fold(f, &mut |g, c : Vec<B>| match g
+    {
+        &Formula::True => B::top(),
+        &Formula::False => B::bottom(),
+        &Formula::Atom(ref a) => env[a].clone(),
+        &Formula::Not(_) => c[0].not(),
+        &Formula::Annotated { .. } => c[0].clone(),
+        &Formula::Implies { .. } => c[0].not().or(&c[1]),
+        &Formula::Iff { .. } => c[0].and(&c[1]).or(&c[0].not().and(&c[1].not())),
+        &Formula::IffAll(_) => all(&c).or(&all(&c.iter().map(|x| x.not()).collect::<Vec<B>>())),
+        &Formula::And(_) => all(&c),
+        &Formula::Or(_) => c.iter().fold(B::bottom(), |acc, x| acc.or(x)),
+    })
+}
+
+// Evaluate under Kleene's strong three-valued logic, where a missing or None atom is unknown.  A result is
+// known whenever the known atoms settle it, e.g. FALSE AND unknown is false and TRUE OR unknown is true.
+fn eval_kleene(f : &Formula, env : &HashMap<char, Option<bool>>) -> Option<bool>
+{
+    fn and(v : &[Option<bool>]) -> Option<bool>
+    {
+        if v.contains(&Some(false)) { Some(false) } else if v.contains(&None) { None } else { Some(true) }
+    }
+    fn or(v : &[Option<bool>]) -> Option<bool>
+    {
+        and(&v.iter().map(|x| x.map(|b| !b)).collect::<Vec<_>>()).map(|b| !b)
+    }
+
+    match f
+    {
+        &Formula::True => Some(true),
+        &Formula::False => Some(false),
+        &Formula::Atom(ref c) => env.get(c).cloned().unwrap_or(None),
+        &Formula::Not(ref n) => eval_kleene(n, env).map(|b| !b),
+        &Formula::Implies { ref l, ref r } => or(&[eval_kleene(l, env).map(|b| !b), eval_kleene(r, env)]),
+        &Formula::Iff { ref l, ref r } => match (eval_kleene(l, env), eval_kleene(r, env))
+        {
+            (Some(a), Some(b)) => Some(a == b),
+            _ => None,
         },
-        Formula::And(v) => Formula::And(v.into_iter().map(|x| simplify1(x)).collect()),
-        Formula::Or(v) => Formula::Or(v.into_iter().map(|x| simplify1(x)).collect()),
+        &Formula::IffAll(ref v) => {
+            let values : Vec<Option<bool>> = v.iter().map(|x| eval_kleene(x, env)).collect();
+            if values.contains(&Some(true)) && values.contains(&Some(false)) {
+                Some(false)
+            } else if values.contains(&None) {
+                None
+            } else {
+                Some(true)
+            }
+        }
+        &Formula::And(ref v) => and(&v.iter().map(|x| eval_kleene(x, env)).collect::<Vec<_>>()),
+        &Formula::Or(ref v) => or(&v.iter().map(|x| eval_kleene(x, env)).collect::<Vec<_>>()),
+        &Formula::Annotated { ref inner, .. } => eval_kleene(inner, env),
     }
 }
 
-fn simplify2(f : Formula) -> Formula
+// Why `check_model` rejected an assignment.
+#[derive(Clone, PartialEq, Debug)]
+enum ModelError {
+    // A subformula the assignment makes false: the formula itself, or the first false conjunct of a
+    // false AND, recursively, e.g. the failing clause of a CNF.
+    Falsified(Formula),
+    // An atom left unassigned on which the value of the formula depends.
+    Incomplete(char),
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self
+        {
+            ModelError::Falsified(ref g) => write!(f, "the assignment falsifies {}", g),
+            ModelError::Incomplete(c) => write!(f, "the assignment leaves {} unset", c),
+        }
+    }
+}
+
+// Check that `model`, which may leave some atoms of `f` unassigned, satisfies `f` whatever values those
+// atoms take, e.g. to validate a model from an external solver.
+fn check_model(f : &Formula, model : &HashMap<char, bool>) -> Result<(), ModelError>
 {
-	match f
-	{
-        g @ Formula::Atom(_) => g,
+    let env : HashMap<char, Option<bool>> = model.iter().map(|(&c, &b)| (c, Some(b))).collect();
+    match eval_kleene(f, &env)
+    {
+        Some(true) => Ok(()),
+        Some(false) => {
+            let mut g = f;
+            while let &Formula::And(ref v) = g {
+                g = v.iter().find(|x| eval_kleene(x, &env) == Some(false)).expect("a false AND has a false conjunct");
+            }
+            Err(ModelError::Falsified(g.clone()))
+        }
+        None => Err(ModelError::Incomplete(atoms(f).into_iter().find(|c| !model.contains_key(c)).expect("only an unset atom is unknown"))),
+    }
+}
 
-        // Remove double-negation.
-        Formula::Not(box Formula::Not(nn)) => simplify2(*nn),
+// One step of a `CompiledFormula`, which works on a stack of values.  The n-ary steps pop their n
+// operands, and every step pushes its result.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Instruction {
+    Const(bool),
+    // The value of atom number i.
+    Atom(usize),
+    Not,
+    Implies,
+    Iff,
+    IffAll(usize),
+    And(usize),
+    Or(usize),
+}
 
-        // Use De Morgan's laws to push down not.  Note that we have to resimplify the new not expression 
-        // after this (they may, for example, form a new double-negation).
-        Formula::Not(box Formula::And(v)) => Formula::Or(v.into_iter().map(|x| simplify2(Formula::Not(box x))).collect()),
-        Formula::Not(box Formula::Or(v)) => Formula::And(v.into_iter().map(|x| simplify2(Formula::Not(box x))).collect()),
+// A formula flattened into postfix instructions over atom numbers, for evaluating it on many
+// assignments: `eval` walks a slice with no recursion and no hashing.  Atom i is `atoms[i]`, and the
+// atoms are those of the formula in sorted order, so an assignment slice is a truth table row.
+#[derive(Clone, PartialEq, Debug)]
+struct CompiledFormula
+{
+    atoms: Vec<char>,
+    code: Vec<Instruction>,
+    // The most values on the stack at once.
+    stack_size: usize,
+}
 
-        g @ Formula::Not(_) => g,
-        Formula::And(v) => Formula::And(v.into_iter().map(|x| simplify2(x)).collect()),
-        Formula::Or(v) => Formula::Or(v.into_iter().map(|x| simplify2(x)).collect()),
-        Formula::Implies { l: _, r: _ } | Formula::Iff { l: _, r: _ } => unimplemented!(),
-	}
+fn compile(f : &Formula) -> CompiledFormula
+{
+    fn emit(f : &Formula, atoms : &[char], code : &mut Vec<Instruction>)
+    {
+        for c in children(f) {
+            emit(c, atoms, code);
+        }
+        let n = children(f).len();
+        let op = match f
+        {
+            &Formula::True => Instruction::Const(true),
+            &Formula::False => Instruction::Const(false),
+            &Formula::Atom(c) => Instruction::Atom(atoms.binary_search(&c).unwrap()),
+            &Formula::Not(_) => Instruction::Not,
+            &Formula::Annotated { .. } => return,
+            &Formula::Implies { .. } => Instruction::Implies,
+            &Formula::Iff { .. } => Instruction::Iff,
+            &Formula::IffAll(_) => Instruction::IffAll(n),
+            &Formula::And(_) => Instruction::And(n),
+            &Formula::Or(_) => Instruction::Or(n),
+        };
+        code.push(op);
+    }
+
+    let atoms = atoms(f);
+    let mut code = Vec::new();
+    emit(f, &atoms, &mut code);
+    let (mut height, mut stack_size) = (0usize, 0usize);
+    for op in &code
+    {
+        height = match *op
+        {
+            Instruction::Const(_) | Instruction::Atom(_) => height + 1,
+            Instruction::Not => height,
+            Instruction::Implies | Instruction::Iff => height - 1,
+            Instruction::IffAll(n) | Instruction::And(n) | Instruction::Or(n) => height + 1 - n,
+        };
+        stack_size = stack_size.max(height);
+    }
+    CompiledFormula { atoms: atoms, code: code, stack_size: stack_size }
 }
 
-#[allow(dead_code)]
-#[allow(unused_variables)]
-fn simplify3(f: Formula) -> (Formula, bool)
+impl CompiledFormula
 {
-	match f
-	{
-        Formula::Implies { l: _, r: _ } | Formula::Iff { l: _, r: _ } => unimplemented!(),
-		g @ Formula::Atom(_) => (g, false),
-		Formula::Not(nn) => 
-		{
-			let (snn, simplified) = simplify3(*nn);
-			(Formula::Not(box snn), simplified)
-		}
+    // The value of the formula when atom i has value `values[i]`.  Panics if `values` is shorter than
+    // `atoms`.
+    fn eval(&self, values : &[bool]) -> bool
+    {
+        assert!(values.len() >= self.atoms.len(), "compiled formula: too few values");
+        let mut stack : Vec<bool> = Vec::with_capacity(self.stack_size);
+        for op in &self.code
+        {
+            let value = match *op
+            {
+                Instruction::Const(b) => b,
+                Instruction::Atom(i) => values[i],
+                Instruction::Not => !stack.pop().unwrap(),
+                Instruction::Implies => { let r = stack.pop().unwrap(); let l = stack.pop().unwrap(); !l || r }
+                Instruction::Iff => { let r = stack.pop().unwrap(); let l = stack.pop().unwrap(); l == r }
+                Instruction::IffAll(n) | Instruction::And(n) | Instruction::Or(n) => {
+                    let start = stack.len() - n;
+                    let value = {
+                        let operands = &stack[start..];
+                        match *op
+                        {
+                            Instruction::IffAll(_) => operands.iter().all(|&x| x) || !operands.iter().any(|&x| x),
+                            Instruction::And(_) => operands.iter().all(|&x| x),
+                            _ => operands.iter().any(|&x| x),
+                        }
+                    };
+                    stack.truncate(start);
+                    value
+                }
+            };
+            stack.push(value);
+        }
+        stack.pop().unwrap()
+    }
+}
 
-		Formula::Or(v) =>
-		{
-			let mut simplified = false;
-			(Formula::Or(v.into_iter().map(|x| { let (sx, s) = simplify3(x); simplified = simplified | s; sx }).collect()), simplified)
-		}
+// Why `f` has the value it does under `model`, as indented lines: each line gives a subformula's value and
+// the subformulas that decide it, which are explained on the lines below it.  For a true OR that is the
+// first true disjunct, for a false AND the first false conjunct, and otherwise every operand.  E.g. for
+// P OR Q with P true:
+//
+//     (P OR Q) is true because P is true
+//       P is true in the model
+//
+// Panics if an atom of `f` is not assigned.
+fn explain_model(f : &Formula, model : &HashMap<char, bool>) -> String
+{
+    fn first<'a>(v : &'a [Formula], model : &HashMap<char, bool>, value : bool) -> Vec<&'a Formula>
+    {
+        v.iter().filter(|x| eval(x, model) == value).take(1).collect()
+    }
 
-        Formula::And(v) => 
+    fn explain(f : &Formula, model : &HashMap<char, bool>, indent : usize, out : &mut String)
+    {
+        if let &Formula::Annotated { ref inner, .. } = f {
+            return explain(inner, model, indent, out);
+        }
+        let value = eval(f, model);
+        let reasons : Vec<&Formula> = match f
         {
-        	// In CNJ, P ^ (Q v S) => (P ^ Q) v (P ^ S).
+            &Formula::True | &Formula::False | &Formula::Atom(_) => vec!(),
+            &Formula::Or(ref v) if value => first(v, model, true),
+            &Formula::And(ref v) if !value => first(v, model, false),
+            // A true implication needs only a false antecedent or a true consequent.
+            &Formula::Implies { ref l, ref r } if value => if eval(l, model) { vec!(r.as_ref()) } else { vec!(l.as_ref()) },
+            // A false IFF(...) needs only one true and one false argument.
+            &Formula::IffAll(ref v) if !value => first(v, model, true).into_iter().chain(first(v, model, false)).collect(),
+            _ => children(f),
+        };
 
-        	// Separate items into disjunctions and others (singles).
-        	let mut simplified = false;
-        	let mut singles = Vec::<Formula>::new();
-        	let mut multiples = Vec::<Vec<Formula>>::new();
-        	for el in v.into_iter().map(|x| { let (sx, s) = simplify3(x); simplified = simplified | s; sx }) 
-        	{
-        		match el 
-        		{
-        			Formula::Or(ov) => { multiples.push(ov); }
-        			g @ _ => { singles.push(g); }
-        		}
-        	}
-            
-            let mut disj = Vec::<Formula>::new();
-        	let iterations = multiples.iter().fold(1, |acc, ref x| acc * x.len());
-        	for i in 0..iterations 
-        	{
-            	let mut conj : Vec<Formula> = singles.iter().cloned().collect();
-        		let mut offset = i;
-        		for ov in &multiples
-        		{
-        			let pick = offset % ov.len();
-        			offset = offset / ov.len();
-        			conj.push(ov[pick].clone());
-        		}
-
-        		disj.push(Formula::And(conj));
-        	}
-
-        	(Formula::Or(disj), simplified || multiples.len() > 0)
+        let because = match f
+        {
+            &Formula::Atom(_) => " in the model".to_string(),
+            _ if reasons.is_empty() => String::new(),
+            _ => format!(" because {}", reasons.iter().map(|r| format!("{} is {}", r, eval(r, model))).collect::<Vec<String>>().join(" and ")),
+        };
+        out.push_str(&format!("{}{} is {}{}\n", "  ".repeat(indent), f, value, because));
+        for r in reasons {
+            explain(r, model, indent + 1, out);
         }
-	}
+    }
+
+    let mut out = String::new();
+    explain(f, model, 0, &mut out);
+    out
 }
 
-fn main() {
-    let nn = Formula::Not(box Formula::Not(box Formula::Atom('A')));
-    println!("{} simplifies to {}", nn, simplify(nn.clone()));
+// The assignment for row `row` of the truth table over `atoms`.  The first atom is the most significant
+// bit, so row 0 is all false and the last row is all true.
+fn assignment(atoms : &[char], row : u64) -> HashMap<char, bool>
+{
+    let n = atoms.len();
+    atoms.iter().enumerate().map(|(i, &c)| (c, (row >> (n - 1 - i)) & 1 == 1)).collect()
+}
 
-    let example = Formula::Implies { l: box Formula::And(vec!(Formula::Atom('P'), Formula::Not(box Formula::Atom('Q')))), r: box Formula::Atom('R') };
-    println!("{} simplifies to {} and then to {}", example, simplify2(simplify1(example.clone())), simplify(example.clone()));
+// Every assignment to the atoms of `f`, as in its truth table but in reflected Gray code order, so that
+// consecutive assignments differ in exactly one atom.  It starts from all false, like the truth table.
+fn models_gray(f : &Formula) -> impl Iterator<Item = HashMap<char, bool>>
+{
+    let atoms = atoms(f);
+    (0..(1u64 << atoms.len())).map(move |i| assignment(&atoms, i ^ (i >> 1)))
+}
 
-    let another = Formula::Iff { l: box Formula::Or(vec!(Formula::Atom('P'), Formula::Atom('Q'))), r: box Formula::Atom('R') };
-    println!("{} simplifies to {} and then to {}", another, simplify2(simplify1(another.clone())), simplify(another.clone()));
+// The sorted union of the atoms of two formulas.
+fn shared_atoms(a : &Formula, b : &Formula) -> Vec<char>
+{
+    let mut all = atoms(a);
+    all.extend(atoms(b));
+    all.sort();
+    all.dedup();
+    all
+}
+
+// True if every assignment satisfying `a` also satisfies `b`, checked over the truth table of their
+// combined atoms.
+fn entails(a : &Formula, b : &Formula) -> bool
+{
+    let atoms = shared_atoms(a, b);
+    (0..(1u64 << atoms.len())).all(|row| {
+        let env = assignment(&atoms, row);
+        !eval(a, &env) || eval(b, &env)
+    })
+}
+
+fn equivalent(a : &Formula, b : &Formula) -> bool
+{
+    let atoms = shared_atoms(a, b);
+    (0..(1u64 << atoms.len())).all(|row| {
+        let env = assignment(&atoms, row);
+        eval(a, &env) == eval(b, &env)
+    })
+}
+
+// `equivalent` for formulas with at most this many atoms between them compares packed truth tables.
+const EQUIVALENT_SMALL_MAX_ATOMS : usize = 16;
+
+// `equivalent`, comparing the two packed truth tables over the atoms of both a word at a time when there
+// are few enough atoms, and falling back to `equivalent` otherwise.
+fn equivalent_small(a : &Formula, b : &Formula) -> bool
+{
+    let atoms = shared_atoms(a, b);
+    if atoms.len() > EQUIVALENT_SMALL_MAX_ATOMS {
+        return equivalent(a, b);
+    }
+    table_bits(a, &atoms) == table_bits(b, &atoms)
+}
+
+// The counterexamples to `equivalent(a, b)`: the assignments to the atoms of both on which `a` and `b`
+// differ, i.e. the models of a XOR b, in truth table order.
+fn diff_models(a : &Formula, b : &Formula) -> Vec<HashMap<char, bool>>
+{
+    let atoms = shared_atoms(a, b);
+    (0..(1u64 << atoms.len())).map(|row| assignment(&atoms, row)).filter(|env| eval(a, env) != eval(b, env)).collect()
+}
+
+// The other models of `f` within Hamming distance `radius` of `model`: those that flip at least one and
+// at most `radius` of the atoms of `f`, nearest first and, at each distance, in the order of the flipped
+// atoms.  Atoms of `model` that `f` does not mention are left alone.  Panics if `model` does not assign
+// every atom of `f`.
+fn solution_neighbors(f : &Formula, model : &HashMap<char, bool>, radius : usize) -> Vec<HashMap<char, bool>>
+{
+    // Flip every choice of `k` more atoms from `atoms[from..]`.
+    fn flips(f : &Formula, atoms : &[char], from : usize, k : usize, env : &mut HashMap<char, bool>, out : &mut Vec<HashMap<char, bool>>)
+    {
+        if k == 0 {
+            if eval(f, env) {
+                out.push(env.clone());
+            }
+            return;
+        }
+        for i in from..atoms.len() {
+            let v = env[&atoms[i]];
+            env.insert(atoms[i], !v);
+            flips(f, atoms, i + 1, k - 1, env, out);
+            env.insert(atoms[i], v);
+        }
+    }
+
+    let atoms = atoms(f);
+    let mut env = model.clone();
+    let mut out = Vec::new();
+    for k in 1..=radius.min(atoms.len()) {
+        flips(f, &atoms, 0, k, &mut env, &mut out);
+    }
+    out
+}
+
+// Bitwise equality of two bit-vectors, given as their atoms from bit 0 up: a[0] <-> b[0] AND ... AND
+// a[n-1] <-> b[n-1], or TRUE for width 0.  Panics if the widths differ.
+fn bv_eq(a : &[char], b : &[char]) -> Formula
+{
+    assert_eq!(a.len(), b.len(), "bv_eq: bit-vectors of different widths");
+    and_of(a.iter().zip(b).map(|(&x, &y)| Formula::Iff { l: box Formula::Atom(x), r: box Formula::Atom(y) }).collect())
+}
+
+// The number of rows of the truth table of `f` (over its own atoms) on which it is true.
+fn count_models(f : &Formula) -> u64
+{
+    let atoms = atoms(f);
+    (0..(1u64 << atoms.len())).filter(|&row| eval(f, &assignment(&atoms, row))).count() as u64
+}
+
+// The Shannon entropy, in bits, of the value of `f` when its atoms are independent fair coin flips: 0 for
+// a constant, 1 for a balanced function such as A XOR B, and in between for the rest.
+fn output_entropy(f : &Formula) -> f64
+{
+    let p = count_models(f) as f64 / (1u64 << atoms(f).len()) as f64;
+    let term = |q : f64| if q == 0.0 { 0.0 } else { -q * q.log2() };
+    term(p) + term(1.0 - p)
+}
+
+// The fraction of `samples` random assignments to the atoms of `f` that satisfy it: an estimate of
+// `count_models(f)` over the number of rows, for formulas with too many atoms to count.  The assignments
+// come from a SplitMix64 generator started at `seed`, so the same seed gives the same estimate.
+fn estimate_sat_fraction(f : &Formula, samples : usize, seed : u64) -> f64
+{
+    assert!(samples > 0, "estimate_sat_fraction: no samples");
+    let atoms = atoms(f);
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    };
+    let mut env = HashMap::new();
+    let mut satisfied = 0;
+    for _ in 0..samples
+    {
+        let mut bits = 0;
+        for (i, &c) in atoms.iter().enumerate() {
+            if i % 64 == 0 {
+                bits = next();
+            }
+            env.insert(c, bits >> (i % 64) & 1 == 1);
+        }
+        if eval(f, &env) {
+            satisfied += 1;
+        }
+    }
+    satisfied as f64 / samples as f64
+}
+
+// A packed sequence of bits, 64 to a word, bit 0 in the lowest bit of the first word.  Bits past `len` in
+// the last word are always clear, so equal bit sequences are equal values.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+struct BitVec
+{
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl BitVec
+{
+    fn len(&self) -> usize
+    {
+        self.len
+    }
+
+    fn get(&self, i : usize) -> bool
+    {
+        assert!(i < self.len, "bit index out of range");
+        (self.words[i / 64] >> (i % 64)) & 1 == 1
+    }
+
+    fn count_ones(&self) -> usize
+    {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+}
+
+// Bit `shift` of the row numbers 0 to 63, as the word whose bit b is bit `shift` of b.
+const ROW_BIT_COLUMNS : [u64; 6] = [
+    0xAAAA_AAAA_AAAA_AAAA,
+    0xCCCC_CCCC_CCCC_CCCC,
+    0xF0F0_F0F0_F0F0_F0F0,
+    0xFF00_FF00_FF00_FF00,
+    0xFFFF_0000_FFFF_0000,
+    0xFFFF_FFFF_0000_0000,
+];
+
+// The output column of the truth table of `f` over its own atoms, packed: bit i is the value of `f` on
+// `assignment(&atoms(f), i)`.
+fn truth_table_bits(f : &Formula) -> BitVec
+{
+    table_bits(f, &atoms(f))
+}
+
+// The output column of the truth table of `f` over `atoms`, which must include all of its atoms.  `f` is
+// evaluated on 64 rows at once, by `eval_in` over words.
+fn table_bits(f : &Formula, atoms : &[char]) -> BitVec
+{
+    let rows = 1usize << atoms.len();
+    let mut words = Vec::with_capacity(rows.div_ceil(64));
+    for w in 0..rows.div_ceil(64)
+    {
+        // The atom for bit `shift` of the row number alternates every 2^shift rows: within a word for the
+        // last six atoms, and from word to word for the others.
+        let columns : HashMap<char, u64> = atoms.iter().enumerate().map(|(i, &c)| {
+            let shift = atoms.len() - 1 - i;
+            let column = if shift < 6 { ROW_BIT_COLUMNS[shift] } else if (w >> (shift - 6)) & 1 == 1 { !0 } else { 0 };
+            (c, column)
+        }).collect();
+        words.push(eval_in(f, &columns));
+    }
+    if rows % 64 != 0 {
+        words[0] &= (1u64 << rows) - 1;
+    }
+    BitVec { words: words, len: rows }
+}
+
+// The don't-care conditions of `f` under `context`: the assignments to the atoms of both that the context
+// rules out, in truth table order.  Since those inputs never occur, `f` may be changed freely on them,
+// e.g. when minimizing it.
+fn dont_cares(f : &Formula, context : &Formula) -> Vec<HashMap<char, bool>>
+{
+    let atoms = shared_atoms(f, context);
+    (0..(1u64 << atoms.len())).map(|row| assignment(&atoms, row)).filter(|env| !eval(context, env)).collect()
+}
+
+// `f` with every occurrence of the atom `var` replaced by `g`.
+fn substitute(f : &Formula, var : char, g : &Formula) -> Formula
+{
+    match f
+    {
+        &Formula::Atom(c) if c == var => g.clone(),
+        &Formula::True | &Formula::False | &Formula::Atom(_) => f.clone(),
+        &Formula::Not(ref n) => Formula::Not(box substitute(n, var, g)),
+        &Formula::Implies { ref l, ref r } => Formula::Implies { l: box substitute(l, var, g), r: box substitute(r, var, g) },
+        &Formula::Iff { ref l, ref r } => Formula::Iff { l: box substitute(l, var, g), r: box substitute(r, var, g) },
+        &Formula::IffAll(ref v) => Formula::IffAll(v.iter().map(|x| substitute(x, var, g)).collect()),
+        &Formula::And(ref v) => Formula::And(v.iter().map(|x| substitute(x, var, g)).collect()),
+        &Formula::Or(ref v) => Formula::Or(v.iter().map(|x| substitute(x, var, g)).collect()),
+        &Formula::Annotated { ref note, ref inner } => Formula::Annotated { note: note.clone(), inner: box substitute(inner, var, g) },
+    }
+}
+
+// `f` with each atom replaced by its image under `map`, all at once; atoms not in `map` are kept.
+fn rename(f : &Formula, map : &HashMap<char, char>) -> Formula
+{
+    fn walk(f : Formula, map : &HashMap<char, char>) -> Formula
+    {
+        match f
+        {
+            Formula::Atom(c) => Formula::Atom(*map.get(&c).unwrap_or(&c)),
+            g @ _ => map_children(g, |x| walk(x, map)),
+        }
+    }
+
+    walk(f.clone(), map)
+}
+
+// The conjunction of two formulas, such as independently written constraint sets.  A shared atom means
+// the same thing in both unless `keep_apart` is set, in which case each atom of `b` that also occurs in
+// `a` is renamed to a fresh atom.  Also returns that renaming of `b`'s atoms (empty without
+// `keep_apart`).
+fn merge(a : &Formula, b : &Formula, keep_apart : bool) -> (Formula, HashMap<char, char>)
+{
+    let mut renaming = HashMap::new();
+    if keep_apart {
+        let mut used : BTreeSet<char> = shared_atoms(a, b).into_iter().collect();
+        let in_a = atoms(a);
+        for c in atoms(b).into_iter().filter(|c| in_a.binary_search(c).is_ok()) {
+            renaming.insert(c, fresh_atom(&mut used));
+        }
+    }
+    (Formula::And(vec!(a.clone(), rename(b, &renaming))), renaming)
+}
+
+// `f` with every occurrence of `var` replaced by the constant `value`.
+fn cofactor(f : &Formula, var : char, value : bool) -> Formula
+{
+    substitute(f, var, if value { &Formula::True } else { &Formula::False })
+}
+
+// Existential quantification: a formula without `var` that is true exactly where `f` is true for some
+// value of `var`, i.e. f[var := true] OR f[var := false], normalized.
+fn exists(f : &Formula, var : char) -> Formula
+{
+    normalize(Formula::Or(vec!(cofactor(f, var, true), cofactor(f, var, false))))
+}
+
+// The Boolean derivative (or difference) of `f` with respect to `var`: f[var := true] XOR f[var := false],
+// normalized, which is true exactly on the assignments to the other atoms where flipping `var` flips `f`.
+// FALSE whenever `f` does not depend on `var`: the cofactors are compared syntactically first, which
+// settles the case where `f` does not mention `var`, and then by truth table.
+fn boolean_derivative(f : &Formula, var : char) -> Formula
+{
+    let (t, e) = (normalize(cofactor(f, var, true)), normalize(cofactor(f, var, false)));
+    if t == e || equivalent(&t, &e) {
+        return Formula::False;
+    }
+    normalize(Formula::Not(box Formula::Iff { l: box t, r: box e }))
+}
+
+// Test patterns for the input `var` of the circuit `f` stuck at 0 and stuck at 1: the assignments to the
+// atoms of `f`, in truth table order, on which the faulty circuit's output differs from the correct one.
+// Those are where the Boolean derivative is true and `var` is set to the opposite of the stuck value.
+// Both are empty if `f` does not depend on `var`.
+fn stuck_at_tests(f : &Formula, var : char) -> (Vec<HashMap<char, bool>>, Vec<HashMap<char, bool>>)
+{
+    let atoms = atoms(f);
+    let derivative = boolean_derivative(f, var);
+    let detecting : Vec<HashMap<char, bool>> = (0..(1u64 << atoms.len())).map(|row| assignment(&atoms, row)).filter(|env| eval(&derivative, env)).collect();
+    detecting.into_iter().partition(|env| env[&var])
+}
+
+// `f` with each of `vars` existentially quantified away in turn, leaving a formula over its other atoms.
+fn forget(f : &Formula, vars : &[char]) -> Formula
+{
+    vars.iter().fold(normalize(f.clone()), |g, &v| exists(&g, v))
+}
+
+// `f` specialized to a partial assignment: the assigned atoms are replaced by constants, which are then
+// folded away, leaving a formula over the unassigned atoms.
+fn partial_eval(f : &Formula, env : &HashMap<char, bool>) -> Formula
+{
+    fn assign(f : Formula, env : &HashMap<char, bool>) -> Formula
+    {
+        match f
+        {
+            Formula::Atom(c) => match env.get(&c)
+            {
+                Some(&true) => Formula::True,
+                Some(&false) => Formula::False,
+                None => Formula::Atom(c),
+            },
+            g @ _ => map_children(g, |x| assign(x, env)),
+        }
+    }
+
+    fold_constants(assign(f.clone(), env))
+}
+
+// Unit propagation on the formula itself, e.g. to shrink it before converting it to CNF: the literals
+// among the top-level conjuncts of `f` fix their atoms, which are then substituted into every other
+// conjunct (and constants folded), until no new literal turns up.  The literals stay in the result, so
+// it is equivalent to `f`; P AND (P -> Q) AND (Q -> R) becomes P AND Q AND R.
+fn propagate_units(f : Formula) -> Formula
+{
+    let mut f = fold_constants(flatten(f));
+    loop
+    {
+        let conjuncts = match f
+        {
+            Formula::And(v) => v,
+            g @ _ => return g,
+        };
+        // `fold_constants` has already turned a pair of complementary literals into FALSE.
+        let units : HashMap<char, bool> = conjuncts.iter().filter_map(literal_of).map(|l| (l.atom, !l.negated)).collect();
+        let assigned = conjuncts.iter().map(|g| if is_literal(g) { g.clone() } else { partial_eval(g, &units) }).collect();
+        let g = fold_constants(flatten(Formula::And(assigned)));
+        if g == Formula::And(conjuncts) {
+            return g;
+        }
+        f = g;
+    }
+}
+
+// True if raising `var` from false to true can never make `f` false, i.e. f[var := false] entails
+// f[var := true].
+fn is_positive_unate(f : &Formula, var : char) -> bool
+{
+    entails(&cofactor(f, var, false), &cofactor(f, var, true))
+}
+
+// True if raising `var` from false to true can never make `f` true.
+fn is_negative_unate(f : &Formula, var : char) -> bool
+{
+    entails(&cofactor(f, var, true), &cofactor(f, var, false))
+}
+
+// The atoms of `f` whose value never affects its truth, i.e. whose two cofactors are equivalent.  Any one
+// of them can be dropped (by substituting either constant) without changing the formula's meaning.
+fn redundant_vars(f : &Formula) -> Vec<char>
+{
+    atoms(f).into_iter().filter(|&v| equivalent(&cofactor(f, v, true), &cofactor(f, v, false))).collect()
+}
+
+// `prune_trivial_subformulas` only checks subformulas with at most this many atoms.
+const PRUNE_MAX_ATOMS : usize = 8;
+
+// `f` with every subformula that is a tautology replaced by TRUE and every contradiction by FALSE, then
+// constants folded, so (X OR ~X) AND Y becomes Y.  Subformulas are checked bottom-up by truth table, which
+// keeps the cost down by skipping any with more than `PRUNE_MAX_ATOMS` atoms.
+fn prune_trivial_subformulas(f : Formula) -> Formula
+{
+    fn prune(f : Formula) -> Formula
+    {
+        let g = map_children(f, prune);
+        if is_literal(&g) || g == Formula::True || g == Formula::False || atoms(&g).len() > PRUNE_MAX_ATOMS {
+            return g;
+        }
+        let bits = truth_table_bits(&g);
+        match bits.count_ones()
+        {
+            0 => Formula::False,
+            n if n == bits.len() => Formula::True,
+            _ => g,
+        }
+    }
+
+    fold_constants(prune(f))
+}
+
+// The most atoms `shortest_equivalent` handles, so that a truth table fits in a word.
+const SHORTEST_MAX_ATOMS : usize = 6;
+
+// A formula with the fewest nodes (as counted by `size`) that is equivalent to `f`, over the atoms of
+// `f`, or None if every one has more than `max_size` nodes.  Candidates are built from TRUE, FALSE, the
+// atoms, NOT, AND and OR of any number of operands, -> and <-> (IFF(...) is not tried), in order of
+// size, and only the first candidate with each truth table is kept as an operand for larger ones, since
+// a smaller equivalent operand can always be swapped in.  The search grows at least exponentially with
+// `max_size`.  Panics if `f` has more than `SHORTEST_MAX_ATOMS` atoms.
+fn shortest_equivalent(f : &Formula, max_size : usize) -> Option<Formula>
+{
+    // The AND (or OR) operand lists with new truth tables whose total size s is the largest size in
+    // `formulas`: a formula or list of size i followed by a formula of size s - i.
+    fn extend_lists(formulas : &[Vec<(u64, Formula)>], lists : &[Vec<(u64, Vec<Formula>)>], and : bool, seen : &mut HashSet<u64>) -> Vec<(u64, Vec<Formula>)>
+    {
+        let s = formulas.len() - 1;
+        let mut layer = Vec::new();
+        for i in 1..s {
+            let firsts = formulas[i].iter().map(|&(t, ref a)| (t, vec!(a.clone())));
+            for (ta, v) in firsts.chain(lists[i].iter().cloned()) {
+                for &(tb, ref b) in &formulas[s - i] {
+                    let t = if and { ta & tb } else { ta | tb };
+                    if seen.insert(t) {
+                        let mut with = v.clone();
+                        with.push(b.clone());
+                        layer.push((t, with));
+                    }
+                }
+            }
+        }
+        layer
+    }
+
+    let atoms = atoms(f);
+    assert!(atoms.len() <= SHORTEST_MAX_ATOMS, "shortest_equivalent: {} atoms is too many", atoms.len());
+    let mask = if atoms.len() == 6 { !0 } else { (1u64 << (1 << atoms.len())) - 1 };
+    let target = table_bits(f, &atoms).words[0];
+
+    // formulas[s]: the formulas of size s with a truth table no smaller formula has.  ands[s] and ors[s]:
+    // the operand lists of at least two formulas, with total size s, whose AND (or OR) has a truth table
+    // no smaller list has.
+    let mut formulas : Vec<Vec<(u64, Formula)>> = vec!(vec!());
+    let mut ands : Vec<Vec<(u64, Vec<Formula>)>> = vec!(vec!());
+    let mut ors : Vec<Vec<(u64, Vec<Formula>)>> = vec!(vec!());
+    let (mut seen, mut seen_and, mut seen_or) = (HashSet::new(), HashSet::new(), HashSet::new());
+    for s in 1..max_size + 1
+    {
+        let mut candidates : Vec<(u64, Formula)> = Vec::new();
+        if s == 1 {
+            candidates.push((mask, Formula::True));
+            candidates.push((0, Formula::False));
+            candidates.extend(atoms.iter().map(|&c| (table_bits(&Formula::Atom(c), &atoms).words[0], Formula::Atom(c))));
+        } else {
+            candidates.extend(formulas[s - 1].iter().map(|&(t, ref g)| (!t & mask, Formula::Not(box g.clone()))));
+            candidates.extend(ands[s - 1].iter().map(|&(t, ref v)| (t, Formula::And(v.clone()))));
+            candidates.extend(ors[s - 1].iter().map(|&(t, ref v)| (t, Formula::Or(v.clone()))));
+            for i in 1..s - 1 {
+                for &(ta, ref a) in &formulas[i] {
+                    for &(tb, ref b) in &formulas[s - 1 - i] {
+                        candidates.push(((!ta | tb) & mask, Formula::Implies { l: box a.clone(), r: box b.clone() }));
+                        candidates.push((!(ta ^ tb) & mask, Formula::Iff { l: box a.clone(), r: box b.clone() }));
+                    }
+                }
+            }
+        }
+        let mut layer = Vec::new();
+        for (t, g) in candidates {
+            if t == target {
+                return Some(g);
+            }
+            if seen.insert(t) {
+                layer.push((t, g));
+            }
+        }
+        formulas.push(layer);
+
+        let and_layer = extend_lists(&formulas, &ands, true, &mut seen_and);
+        ands.push(and_layer);
+        let or_layer = extend_lists(&formulas, &ors, false, &mut seen_or);
+        ors.push(or_layer);
+    }
+    None
+}
+
+// True if `f` equals its own dual, f(x) = ~f(~x) for every assignment x, as majority does.  Negating every
+// atom turns row i of the truth table into row 2^n - 1 - i, so the table read backwards must be the
+// complement of the table.
+fn is_self_dual(f : &Formula) -> bool
+{
+    let bits = truth_table_bits(f);
+    let last = bits.len() - 1;
+    (0..bits.len()).all(|i| bits.get(i) != bits.get(last - i))
+}
+
+// The sets of at least two atoms of `f` that can be permuted among themselves without changing its truth
+// table, e.g. all the atoms of A AND B AND C, each sorted and in order of their first atoms.  Each pair is
+// checked by swapping the two atoms, and the pairs that can be swapped are closed under transitivity
+// (swaps generate every permutation of a group).
+fn symmetric_groups(f : &Formula) -> Vec<Vec<char>>
+{
+    let atoms = atoms(f);
+    // group[i]: the index of the first atom known to be symmetric to atoms[i].
+    let mut group : Vec<usize> = (0..atoms.len()).collect();
+    for i in 0..atoms.len() {
+        for j in (i + 1)..atoms.len() {
+            if group[j] != j {
+                continue;
+            }
+            let swap : HashMap<char, char> = vec!((atoms[i], atoms[j]), (atoms[j], atoms[i])).into_iter().collect();
+            if equivalent_small(f, &rename(f, &swap)) {
+                group[j] = group[i];
+            }
+        }
+    }
+    (0..atoms.len()).filter(|&i| group[i] == i).map(|i| {
+        (0..atoms.len()).filter(|&j| group[j] == i).map(|j| atoms[j]).collect::<Vec<char>>()
+    }).filter(|g| g.len() > 1).collect()
+}
+
+// Clauses that break the symmetries `symmetric_groups` finds: within each group the atoms are ordered
+// false before true, by a clause ~x v y for each atom x and the next atom y of its group.  Any model of `f`
+// can be permuted within the groups into one that satisfies them, so conjoining them with `f` keeps it
+// satisfiable exactly when it was, while leaving the solver fewer models to search.
+fn symmetry_breaking_clauses(f : &Formula) -> Vec<Vec<Literal>>
+{
+    symmetric_groups(f).iter().flat_map(|g| g.windows(2).map(|w| {
+        vec!(Literal { atom: w[0], negated: true }, Literal { atom: w[1], negated: false })
+    }).collect::<Vec<_>>()).collect()
+}
+
+// The most atoms (that it depends on) for which `to_read_once` searches for a read-once form.
+const READ_ONCE_MAX_ATOMS : usize = 10;
+
+// A formula equivalent to `f` over AND, OR and NOT in which every atom occurs at most once, or None if
+// there is none (as for A XOR B).  Atoms `f` does not depend on are dropped first.  A read-once function
+// of two or more atoms is the AND or the OR of functions of disjoint sets of atoms, so the atoms are split
+// every way into two sets S and T, and f is the AND of the parts exactly when it is equivalent to
+// (EXISTS T. f) AND (EXISTS S. f), and the OR exactly when it is equivalent to (FORALL T. f) OR
+// (FORALL S. f); the parts are then put in read-once form in turn.  Panics if `f` depends on more than
+// `READ_ONCE_MAX_ATOMS` atoms.
+fn to_read_once(f : &Formula) -> Option<Formula>
+{
+    fn read_once(f : &Formula, atoms : &[char]) -> Option<Formula>
+    {
+        match atoms.len()
+        {
+            0 => return Some(if eval(f, &HashMap::new()) { Formula::True } else { Formula::False }),
+            1 => return Some(if equivalent(f, &Formula::Atom(atoms[0])) { Formula::Atom(atoms[0]) } else { Formula::Not(box Formula::Atom(atoms[0])) }),
+            _ => {}
+        }
+        let forall = |g : &Formula, vars : &[char]| Formula::Not(box forget(&Formula::Not(box g.clone()), vars));
+        // `atoms[0]` is always in the first set, so each split is tried once.
+        for mask in 0..(1u64 << (atoms.len() - 1)) - 1
+        {
+            let in_s = |i : usize| i == 0 || (mask >> (i - 1)) & 1 == 1;
+            let s : Vec<char> = (0..atoms.len()).filter(|&i| in_s(i)).map(|i| atoms[i]).collect();
+            let t : Vec<char> = (0..atoms.len()).filter(|&i| !in_s(i)).map(|i| atoms[i]).collect();
+            for &and in &[true, false]
+            {
+                let (g, h) = if and { (forget(f, &t), forget(f, &s)) } else { (forall(f, &t), forall(f, &s)) };
+                let joined = if and { Formula::And(vec!(g.clone(), h.clone())) } else { Formula::Or(vec!(g.clone(), h.clone())) };
+                if equivalent_small(f, &joined) {
+                    let (g, h) = (read_once(&g, &s)?, read_once(&h, &t)?);
+                    return Some(if and { Formula::And(vec!(g, h)) } else { Formula::Or(vec!(g, h)) });
+                }
+            }
+        }
+        None
+    }
+
+    let redundant = redundant_vars(f);
+    let g = redundant.iter().fold(f.clone(), |g, &v| cofactor(&g, v, false));
+    let atoms : Vec<char> = atoms(f).into_iter().filter(|c| !redundant.contains(c)).collect();
+    assert!(atoms.len() <= READ_ONCE_MAX_ATOMS, "to_read_once: {} atoms is too many", atoms.len());
+    read_once(&g, &atoms).map(flatten)
+}
+
+// A boolean function in algebraic normal form: the XOR of `terms`, each the AND of its atoms (sorted), so
+// the empty term is TRUE and no terms at all is FALSE.  Every function has exactly one such form, with the
+// terms sorted by degree and then by their atoms as `to_anf` leaves them.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+struct AnfPolynomial
+{
+    terms: Vec<Vec<char>>,
+}
+
+impl AnfPolynomial
+{
+    // The highest number of atoms in a term, or 0 for a constant.
+    fn degree(&self) -> usize
+    {
+        self.terms.iter().map(|t| t.len()).max().unwrap_or(0)
+    }
+
+    // The polynomial as a formula, writing a XOR b as ~(a <-> b).
+    fn to_formula(&self) -> Formula
+    {
+        let term = |t : &Vec<char>| and_of(t.iter().map(|&c| Formula::Atom(c)).collect());
+        let mut terms = self.terms.iter().map(term);
+        match terms.next()
+        {
+            None => Formula::False,
+            Some(first) => terms.fold(first, |acc, t| Formula::Not(box Formula::Iff { l: box acc, r: box t })),
+        }
+    }
+}
+
+impl fmt::Display for AnfPolynomial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.terms.is_empty() {
+            return write!(f, "FALSE");
+        }
+        let term = |t : &Vec<char>| match t.len()
+        {
+            0 => "TRUE".to_string(),
+            1 => t[0].to_string(),
+            _ => format!("({})", t.iter().map(|c| c.to_string()).collect::<Vec<String>>().join(" AND ")),
+        };
+        write!(f, "{}", self.terms.iter().map(term).collect::<Vec<String>>().join(" XOR "))
+    }
+}
+
+// The algebraic normal form of `f` over its own atoms, from the Möbius transform of its truth table: the
+// coefficient of the term over a set of atoms is the XOR of `f` on every row whose true atoms are a subset
+// of that set.
+fn to_anf(f : &Formula) -> AnfPolynomial
+{
+    let atoms = atoms(f);
+    let n = atoms.len();
+    let bits = truth_table_bits(f);
+    let mut coefficients : Vec<bool> = (0..bits.len()).map(|i| bits.get(i)).collect();
+    for k in 0..n
+    {
+        let bit = 1 << k;
+        for row in 0..coefficients.len() {
+            if row & bit != 0 {
+                coefficients[row] ^= coefficients[row ^ bit];
+            }
+        }
+    }
+
+    // The first atom is the most significant bit of a row.
+    let mut terms : Vec<Vec<char>> = (0..coefficients.len()).filter(|&row| coefficients[row])
+        .map(|row| (0..n).filter(|&i| (row >> (n - 1 - i)) & 1 == 1).map(|i| atoms[i]).collect())
+        .collect();
+    terms.sort_by(|a : &Vec<char>, b : &Vec<char>| a.len().cmp(&b.len()).then(a.cmp(b)));
+    AnfPolynomial { terms: terms }
+}
+
+// True if `f` is affine: the XOR of some of its atoms, possibly negated, i.e. its algebraic normal form
+// has degree at most 1.  These are the functions `solve_xor` handles.
+fn is_affine(f : &Formula) -> bool
+{
+    to_anf(f).degree() <= 1
+}
+
+// The most atoms `from_fn` will enumerate; its table has 2^n rows.
+const FROM_FN_MAX_ATOMS : usize = 20;
+
+// A formula over `atoms` that is true exactly where `f` is, found by sampling `f` on every row of the truth
+// table.  The result is the disjunction of the true rows' minterms (so its size grows with the number of
+// true rows, not with the complexity of `f`).  Panics on more than `FROM_FN_MAX_ATOMS` atoms.
+fn from_fn(atoms : &[char], f : impl Fn(&HashMap<char, bool>) -> bool) -> Formula
+{
+    assert!(atoms.len() <= FROM_FN_MAX_ATOMS, "from_fn: {} atoms is too many to enumerate", atoms.len());
+    let minterms = (0..(1u64 << atoms.len())).map(|row| assignment(atoms, row)).filter(|env| f(env)).map(|env| {
+        and_of(atoms.iter().map(|&c| if env[&c] { Formula::Atom(c) } else { Formula::Not(box Formula::Atom(c)) }).collect())
+    }).collect();
+    or_of(minterms)
+}
+
+// The influence of each atom of `f`: the fraction of the rows of its truth table on which flipping that
+// atom alone changes the value of `f`.  Redundant atoms have influence 0, and every atom of a parity
+// function has influence 1.
+fn influence(f : &Formula) -> HashMap<char, f64>
+{
+    let atoms = atoms(f);
+    let rows = 1u64 << atoms.len();
+    let values : Vec<bool> = (0..rows).map(|row| eval(f, &assignment(&atoms, row))).collect();
+    atoms.iter().enumerate().map(|(i, &c)| {
+        // Flipping atom i flips this bit of the row number.
+        let bit = 1u64 << (atoms.len() - 1 - i);
+        let sensitive = (0..rows).filter(|&row| values[row as usize] != values[(row ^ bit) as usize]).count();
+        (c, sensitive as f64 / rows as f64)
+    }).collect()
+}
+
+fn simplify(f : Formula) -> Formula
+{
+	let (sf, _) = simplify3(simplify2(simplify1(f)));
+	sf
+}
+
+// Steps 1 and 2 only: the negation normal form, with implications removed and negations on atoms.
+fn to_nnf(f : Formula) -> Formula
+{
+    simplify2(simplify1(f))
+}
+
+// `size(&to_nnf(f.clone()))`, computed without building the NNF (and saturating instead of overflowing, so
+// it is safe on formulas whose NNF could never be built).  Each node gets the sizes of its NNF and of its
+// negation's NNF; <-> and IFF(...) count both for their operands, since their expansions use each
+// operand both ways round.
+fn nnf_size_estimate(f : &Formula) -> usize
+{
+    let sum = |v : &[(usize, usize)], pick : fn(&(usize, usize)) -> usize| v.iter().map(pick).fold(0usize, |a, b| a.saturating_add(b));
+    let (positive, _) = fold(f, &mut |g, c : Vec<(usize, usize)>| match g
+    {
+        &Formula::True | &Formula::False => (1, 1),
+        &Formula::Atom(_) => (1, 2),
+        &Formula::Not(_) => (c[0].1, c[0].0),
+        // Stripped by `to_nnf`.
+        &Formula::Annotated { .. } => c[0],
+        &Formula::And(_) | &Formula::Or(_) => (sum(&c, |x| x.0).saturating_add(1), sum(&c, |x| x.1).saturating_add(1)),
+        // l -> r ~> ~l v r, and its negation l ^ ~r
+        &Formula::Implies { .. } => (c[0].1.saturating_add(c[1].0).saturating_add(1), c[0].0.saturating_add(c[1].1).saturating_add(1)),
+        &Formula::Iff { .. } | &Formula::IffAll(_) => {
+            let both = sum(&c, |x| x.0).saturating_add(sum(&c, |x| x.1)).saturating_add(3);
+            (both, both)
+        }
+    });
+    positive
+}
+
+// The NNF of ~f.
+fn negate(f : Formula) -> Formula
+{
+    to_nnf(Formula::Not(box f))
+}
+
+// Turn `f` into `negate(f)` (with an identical result) by reusing its nodes: AND and OR swap their child
+// vectors over and negations are pushed down in place, so only new negations of atoms and the operands of
+// expanded implications allocate.  For hot loops that negate the same formulas repeatedly.
+fn negate_in_place(f : &mut Formula)
+{
+    let negated = match f
+    {
+        &mut Formula::True => Formula::False,
+        &mut Formula::False => Formula::True,
+        &mut Formula::Atom(c) => Formula::Not(box Formula::Atom(c)),
+        &mut Formula::Not(ref mut n) => { nnf_in_place(n); take(n) }
+        &mut Formula::Annotated { ref mut inner, .. } => { negate_in_place(inner); take(inner) }
+        &mut Formula::And(ref mut v) => { for x in v.iter_mut() { negate_in_place(x); } Formula::Or(mem::take(v)) }
+        &mut Formula::Or(ref mut v) => { for x in v.iter_mut() { negate_in_place(x); } Formula::And(mem::take(v)) }
+
+        // ~(l -> r) ~> l ^ ~r
+        &mut Formula::Implies { ref mut l, ref mut r } => {
+            nnf_in_place(l);
+            negate_in_place(r);
+            Formula::And(vec!(take(l), take(r)))
+        }
+
+        // ~IFF(x1, ..., xn) ~> (~x1 v ... v ~xn) ^ (x1 v ... v xn)
+        &mut Formula::IffAll(ref mut v) => {
+            let mut nv = v.clone();
+            for x in nv.iter_mut() { negate_in_place(x); }
+            for x in v.iter_mut() { nnf_in_place(x); }
+            Formula::And(vec!(Formula::Or(nv), Formula::Or(mem::take(v))))
+        }
+
+        // ~(l <-> r) ~> (l ^ ~r) v (r ^ ~l)
+        &mut Formula::Iff { ref mut l, ref mut r } => {
+            let mut nl = (**l).clone();
+            let mut nr = (**r).clone();
+            nnf_in_place(l);
+            nnf_in_place(r);
+            negate_in_place(&mut nl);
+            negate_in_place(&mut nr);
+            Formula::Or(vec!(Formula::And(vec!(take(l), nr)), Formula::And(vec!(take(r), nl))))
+        }
+    };
+    *f = negated;
+}
+
+// The in-place counterpart of `to_nnf`.
+fn nnf_in_place(f : &mut Formula)
+{
+    let nnf = match f
+    {
+        &mut Formula::True | &mut Formula::False | &mut Formula::Atom(_) => return,
+        &mut Formula::Not(ref mut n) => { negate_in_place(n); take(n) }
+        &mut Formula::Annotated { ref mut inner, .. } => { nnf_in_place(inner); take(inner) }
+        &mut Formula::And(ref mut v) | &mut Formula::Or(ref mut v) => { for x in v.iter_mut() { nnf_in_place(x); } return; }
+
+        // l -> r ~> ~l v r
+        &mut Formula::Implies { ref mut l, ref mut r } => {
+            negate_in_place(l);
+            nnf_in_place(r);
+            Formula::Or(vec!(take(l), take(r)))
+        }
+
+        // IFF(x1, ..., xn) ~> (x1 ^ ... ^ xn) v (~x1 ^ ... ^ ~xn)
+        &mut Formula::IffAll(ref mut v) => {
+            let mut nv = v.clone();
+            for x in nv.iter_mut() { negate_in_place(x); }
+            for x in v.iter_mut() { nnf_in_place(x); }
+            Formula::Or(vec!(Formula::And(mem::take(v)), Formula::And(nv)))
+        }
+
+        // l <-> r ~> (~l v r) ^ (~r v l)
+        &mut Formula::Iff { ref mut l, ref mut r } => {
+            let mut nl = (**l).clone();
+            let mut nr = (**r).clone();
+            nnf_in_place(l);
+            nnf_in_place(r);
+            negate_in_place(&mut nl);
+            negate_in_place(&mut nr);
+            Formula::And(vec!(Formula::Or(vec!(nl, take(r))), Formula::Or(vec!(nr, take(l)))))
+        }
+    };
+    *f = nnf;
+}
+
+// Move a formula out from behind a reference, leaving a placeholder.
+fn take(f : &mut Formula) -> Formula
+{
+    mem::replace(f, Formula::True)
+}
+
+fn simplify1(f : Formula) -> Formula
+{
+    match f
+    {
+        g @ Formula::True | g @ Formula::False | g @ Formula::Atom(_) => g,
+        Formula::Not(n) => Formula::Not(box simplify1(*n)),
+        Formula::Implies { l, r } => Formula::Or(vec!(Formula::Not(box simplify1(*l)), simplify1(*r))),
+        Formula::Iff { l, r } => {
+            let ls = simplify1(*l);
+            let rs = simplify1(*r);
+            let nl = Formula::Or(vec!(Formula::Not(box ls.clone()), rs.clone()));
+            let nr = Formula::Or(vec!(Formula::Not(box rs), ls));
+            Formula::And(vec!(nl, nr))
+        },
+        // Either all of them are true or all of them are false.
+        Formula::IffAll(v) => {
+            let vs : Vec<Formula> = v.into_iter().map(|x| simplify1(x)).collect();
+            let nvs = vs.iter().map(|x| Formula::Not(box x.clone())).collect();
+            Formula::Or(vec!(Formula::And(vs), Formula::And(nvs)))
+        },
+        Formula::And(v) => Formula::And(v.into_iter().map(|x| simplify1(x)).collect()),
+        Formula::Or(v) => Formula::Or(v.into_iter().map(|x| simplify1(x)).collect()),
+        Formula::Annotated { inner, .. } => simplify1(*inner),
+    }
+}
+
+fn simplify2(f : Formula) -> Formula
+{
+	match f
+	{
+        g @ Formula::True | g @ Formula::False | g @ Formula::Atom(_) => g,
+
+        // Remove double-negation.
+        Formula::Not(box Formula::Not(nn)) => simplify2(*nn),
+
+        // Use De Morgan's laws to push down not.  Note that we have to resimplify the new not expression 
+        // after this (they may, for example, form a new double-negation).
+        Formula::Not(box Formula::And(v)) => Formula::Or(v.into_iter().map(|x| simplify2(Formula::Not(box x))).collect()),
+        Formula::Not(box Formula::Or(v)) => Formula::And(v.into_iter().map(|x| simplify2(Formula::Not(box x))).collect()),
+
+        Formula::Not(box Formula::True) => Formula::False,
+        Formula::Not(box Formula::False) => Formula::True,
+
+        // Step 1 expands IFF(...), but a formula that skipped it may still hold one.
+        g @ Formula::IffAll(_) | g @ Formula::Not(box Formula::IffAll(_)) => simplify2(simplify1(g)),
+        // Notes are dropped, as step 1 drops them.
+        Formula::Annotated { inner, .. } => simplify2(*inner),
+        Formula::Not(box Formula::Annotated { inner, .. }) => simplify2(Formula::Not(inner)),
+
+        g @ Formula::Not(_) => g,
+        Formula::And(v) => Formula::And(v.into_iter().map(|x| simplify2(x)).collect()),
+        Formula::Or(v) => Formula::Or(v.into_iter().map(|x| simplify2(x)).collect()),
+        Formula::Implies { l: _, r: _ } | Formula::Iff { l: _, r: _ } => unimplemented!(),
+	}
+}
+
+fn simplify3(f: Formula) -> (Formula, bool)
+{
+	simplify3_within(f, usize::MAX).expect("distribution overflowed")
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum SimplifyError {
+	// Distributing an AND would have produced more than the allowed number of clauses.
+	Explosion,
+}
+
+impl fmt::Display for SimplifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self
+        {
+            SimplifyError::Explosion => write!(f, "distribution produced too many clauses"),
+        }
+    }
+}
+
+// `simplify`, giving up with `SimplifyError::Explosion` as soon as distributing one AND would produce more
+// than `max_clauses` clauses, instead of running out of time or memory.  A caller can then fall back to
+// an encoding that does not distribute.
+fn try_simplify(f : Formula, max_clauses : usize) -> Result<Formula, SimplifyError>
+{
+	let (sf, _) = simplify3_within(simplify2(simplify1(f)), max_clauses)?;
+	Ok(sf)
+}
+
+// The simplifiers recurse once per level of the formula (a small constant number of times, once <-> and
+// IFF(...) are expanded), so a deep enough formula overflows the stack.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct DepthExceeded {
+	max_depth: usize,
+}
+
+impl fmt::Display for DepthExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "formula is deeper than {} levels", self.max_depth)
+    }
+}
+
+// `simplify`, refusing with `DepthExceeded` any formula whose `depth` is more than `max_depth` instead
+// of recursing into it.  The depth is measured without recursion, and `f` is only borrowed, since
+// dropping a refused formula here would recurse through every level of it.
+fn simplify_depth_limited(f : &Formula, max_depth : usize) -> Result<Formula, DepthExceeded>
+{
+	let mut stack = vec!((f, 1));
+	while let Some((g, d)) = stack.pop()
+	{
+		if d > max_depth {
+			return Err(DepthExceeded { max_depth: max_depth });
+		}
+		stack.extend(children(g).into_iter().map(|c| (c, d + 1)));
+	}
+	Ok(simplify(f.clone()))
+}
+
+fn simplify3_within(f: Formula, max_clauses: usize) -> Result<(Formula, bool), SimplifyError>
+{
+	match f
+	{
+        Formula::Implies { l: _, r: _ } | Formula::Iff { l: _, r: _ } => unimplemented!(),
+		// As in `simplify2`.
+		g @ Formula::IffAll(_) | g @ Formula::Not(box Formula::IffAll(_)) => simplify3_within(simplify2(simplify1(g)), max_clauses),
+		Formula::Annotated { inner, .. } => simplify3_within(*inner, max_clauses),
+		g @ Formula::Not(box Formula::Annotated { .. }) => simplify3_within(simplify2(g), max_clauses),
+		g @ Formula::True | g @ Formula::False | g @ Formula::Atom(_) => Ok((g, false)),
+		Formula::Not(nn) => 
+		{
+			let (snn, simplified) = simplify3_within(*nn, max_clauses)?;
+			Ok((Formula::Not(box snn), simplified))
+		}
+
+		Formula::Or(v) =>
+		{
+			let mut simplified = false;
+			let mut sv = Vec::new();
+			for x in v { let (sx, s) = simplify3_within(x, max_clauses)?; simplified = simplified | s; sv.push(sx); }
+			Ok((Formula::Or(sv), simplified))
+		}
+
+        Formula::And(v) => 
+        {
+        	let mut simplified = false;
+        	let mut sv = Vec::new();
+        	for x in v { let (sx, s) = simplify3_within(x, max_clauses)?; simplified = simplified | s; sv.push(sx); }
+        	let distributed = sv.iter().any(|x| match x { &Formula::Or(_) => true, _ => false });
+        	Ok((distribute_within(sv, max_clauses)?, simplified || distributed))
+        }
+	}
+}
+
+fn distribute(v : Vec<Formula>) -> Formula
+{
+	distribute_within(v, usize::MAX).expect("distribution overflowed")
+}
+
+// In CNJ, P ^ (Q v S) => (P ^ Q) v (P ^ S).  An AND with no OR among `v` becomes a one-term OR.  Fails,
+// before building anything, if there would be more than `max_clauses` terms.
+fn distribute_within(v : Vec<Formula>, max_clauses : usize) -> Result<Formula, SimplifyError>
+{
+	// Separate items into disjunctions and others (singles).
+	let mut singles = Vec::<Formula>::new();
+	let mut multiples = Vec::<Vec<Formula>>::new();
+	for el in v
+	{
+		match el 
+		{
+			Formula::Or(ov) => { multiples.push(ov); }
+			g @ _ => { singles.push(g); }
+		}
+	}
+    
+    let mut disj = Vec::<Formula>::new();
+	let iterations = match multiples.iter().try_fold(1usize, |acc, ref x| acc.checked_mul(x.len()))
+	{
+		Some(n) if n <= max_clauses => n,
+		_ => return Err(SimplifyError::Explosion),
+	};
+	for i in 0..iterations 
+	{
+    	let mut conj : Vec<Formula> = singles.iter().cloned().collect();
+		let mut offset = i;
+		for ov in &multiples
+		{
+			let pick = offset % ov.len();
+			offset = offset / ov.len();
+			conj.push(ov[pick].clone());
+		}
+
+		disj.push(Formula::And(conj));
+	}
+
+	Ok(Formula::Or(disj))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Law {
+    Implication,
+    Biconditional,
+    IffAll,
+    DoubleNegation,
+    DeMorgan,
+    NegatedConstant,
+    Distribution,
+    // An AND with no OR among its children wrapped into an OR of that one term, the shape step 3 leaves
+    // every AND in.
+    OneTerm,
+    // An annotation dropped in step 1.
+    StripAnnotation,
+}
+
+impl fmt::Display for Law {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self
+        {
+            Law::Implication => "implication",
+            Law::Biconditional => "biconditional",
+            Law::IffAll => "IFF expansion",
+            Law::DoubleNegation => "double negation",
+            Law::DeMorgan => "De Morgan",
+            Law::NegatedConstant => "negated constant",
+            Law::Distribution => "distribution",
+            Law::OneTerm => "one-term OR",
+            Law::StripAnnotation => "strip annotation",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// One rewrite made by `simplify_with_trace`: the subformula at `path` (child indices from the root, in
+// the order of `children`) was `before` and became `after`.
+#[derive(Clone, PartialEq, Debug)]
+struct RewriteStep
+{
+    law: Law,
+    path: Vec<usize>,
+    before: Formula,
+    after: Formula,
+}
+
+impl fmt::Display for RewriteStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} => {}", self.law, self.before, self.after)
+    }
+}
+
+// `simplify`, also returning every rewrite it made in order.  Each step is an equivalence, so printing
+// them gives a proof that the input equals the result; `replay` applies them again.
+fn simplify_with_trace(f : Formula) -> (Formula, Vec<RewriteStep>)
+{
+    fn record(f : &mut Formula, law : Law, after : Formula, path : &[usize], steps : &mut Vec<RewriteStep>)
+    {
+        steps.push(RewriteStep { law: law, path: path.to_vec(), before: f.clone(), after: after.clone() });
+        *f = after;
+    }
+
+    // Step 1, as in `simplify1`: children first, then the node itself.
+    fn remove_implications(f : &mut Formula, path : &mut Vec<usize>, steps : &mut Vec<RewriteStep>)
+    {
+        for (i, c) in children_mut(f).into_iter().enumerate() {
+            path.push(i);
+            remove_implications(c, path, steps);
+            path.pop();
+        }
+        let rewrite = match f
+        {
+            &mut Formula::Implies { ref l, ref r } => Some((Law::Implication, Formula::Or(vec!(Formula::Not(l.clone()), (**r).clone())))),
+            &mut Formula::Iff { ref l, ref r } => {
+                let nl = Formula::Or(vec!(Formula::Not(l.clone()), (**r).clone()));
+                let nr = Formula::Or(vec!(Formula::Not(r.clone()), (**l).clone()));
+                Some((Law::Biconditional, Formula::And(vec!(nl, nr))))
+            }
+            &mut Formula::IffAll(ref v) => {
+                let nvs = v.iter().map(|x| Formula::Not(box x.clone())).collect();
+                Some((Law::IffAll, Formula::Or(vec!(Formula::And(v.clone()), Formula::And(nvs)))))
+            }
+            &mut Formula::Annotated { ref inner, .. } => Some((Law::StripAnnotation, (**inner).clone())),
+            _ => None,
+        };
+        if let Some((law, after)) = rewrite {
+            record(f, law, after, path, steps);
+        }
+    }
+
+    // Step 2, as in `simplify2`: the node first, then whatever it became.
+    fn push_negations(f : &mut Formula, path : &mut Vec<usize>, steps : &mut Vec<RewriteStep>)
+    {
+        let rewrite = match f
+        {
+            &mut Formula::Not(ref n) => match **n
+            {
+                Formula::Not(ref nn) => Some((Law::DoubleNegation, (**nn).clone())),
+                Formula::And(ref v) => Some((Law::DeMorgan, Formula::Or(v.iter().map(|x| Formula::Not(box x.clone())).collect()))),
+                Formula::Or(ref v) => Some((Law::DeMorgan, Formula::And(v.iter().map(|x| Formula::Not(box x.clone())).collect()))),
+                Formula::True => Some((Law::NegatedConstant, Formula::False)),
+                Formula::False => Some((Law::NegatedConstant, Formula::True)),
+                _ => None,
+            },
+            _ => None,
+        };
+        match rewrite
+        {
+            Some((law, after)) => {
+                record(f, law, after, path, steps);
+                push_negations(f, path, steps);
+            }
+            None => for (i, c) in children_mut(f).into_iter().enumerate() {
+                path.push(i);
+                push_negations(c, path, steps);
+                path.pop();
+            },
+        }
+    }
+
+    // Step 3, as in `simplify3`: children first, then distribute each AND, which for an AND with no OR
+    // child only wraps it in a one-term OR.
+    fn distribute_ands(f : &mut Formula, path : &mut Vec<usize>, steps : &mut Vec<RewriteStep>)
+    {
+        for (i, c) in children_mut(f).into_iter().enumerate() {
+            path.push(i);
+            distribute_ands(c, path, steps);
+            path.pop();
+        }
+        let distributed = match f
+        {
+            &mut Formula::And(ref v) => {
+                let law = if v.iter().any(|x| match x { &Formula::Or(_) => true, _ => false }) { Law::Distribution } else { Law::OneTerm };
+                Some((law, distribute(v.clone())))
+            }
+            _ => None,
+        };
+        if let Some((law, after)) = distributed {
+            record(f, law, after, path, steps);
+        }
+    }
+
+    let mut f = f;
+    let mut steps = Vec::new();
+    remove_implications(&mut f, &mut vec!(), &mut steps);
+    push_negations(&mut f, &mut vec!(), &mut steps);
+    distribute_ands(&mut f, &mut vec!(), &mut steps);
+    (f, steps)
+}
+
+// How many times each kind of rewrite fired during `simplify_with_stats`.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+struct SimplifyStats
+{
+    // ->, <-> and IFF(...) expanded into AND, OR and NOT.
+    expansions: usize,
+    double_negations: usize,
+    de_morgans: usize,
+    // Negations of TRUE or FALSE.
+    constant_folds: usize,
+    // ANDs distributed over at least one OR.
+    distributions: usize,
+}
+
+// `simplify`, counting the rewrites it makes.
+fn simplify_with_stats(f : Formula) -> (Formula, SimplifyStats)
+{
+    let (g, steps) = simplify_with_trace(f);
+    let mut stats = SimplifyStats::default();
+    for step in &steps
+    {
+        match step.law
+        {
+            Law::Implication | Law::Biconditional | Law::IffAll => stats.expansions += 1,
+            Law::DoubleNegation => stats.double_negations += 1,
+            Law::DeMorgan => stats.de_morgans += 1,
+            Law::NegatedConstant => stats.constant_folds += 1,
+            Law::Distribution => stats.distributions += 1,
+            Law::OneTerm | Law::StripAnnotation => {}
+        }
+    }
+    (g, stats)
+}
+
+// Apply the steps of a `simplify_with_trace` trace to `f`, which should be the formula that was traced.
+// Panics if a step does not find its `before` at its path.
+fn replay(f : &Formula, steps : &[RewriteStep]) -> Formula
+{
+    let mut f = f.clone();
+    for step in steps
+    {
+        let mut node = &mut f;
+        for &i in &step.path {
+            node = children_mut(node).into_iter().nth(i).expect("step path leads outside the formula");
+        }
+        assert!(*node == step.before, "step does not match the formula: expected {}, found {}", step.before, node);
+        *node = step.after.clone();
+    }
+    f
+}
+
+// Memoized `simplify`, for tools that simplify many overlapping formulas.  Entries are keyed by the
+// `normalize`d input, so inputs equal up to associativity, commutativity and repeats share one entry (and
+// get the result simplifying the first of them gave, which is equivalent to all of them).
+struct SimplifyCache
+{
+    entries: HashMap<Formula, Formula>,
+    hits: u64,
+    misses: u64,
+}
+
+impl SimplifyCache
+{
+    fn new() -> SimplifyCache
+    {
+        SimplifyCache { entries: HashMap::new(), hits: 0, misses: 0 }
+    }
+
+    fn simplify(&mut self, f : Formula) -> Formula
+    {
+        let key = normalize(f.clone());
+        if let Some(g) = self.entries.get(&key) {
+            self.hits += 1;
+            return g.clone();
+        }
+        self.misses += 1;
+        let g = simplify(f);
+        self.entries.insert(key, g.clone());
+        g
+    }
+
+    fn len(&self) -> usize
+    {
+        self.entries.len()
+    }
+
+    fn clear(&mut self)
+    {
+        self.entries.clear();
+    }
+}
+
+// Write the truth table of a formula one row at a time, so that nothing proportional to the number of rows
+// is ever held in memory.  The header lists the atoms followed by the formula itself, and each row gives
+// the atom values and the result as 0/1, e.g. for P -> Q:
+//
+//     P Q | P -> Q
+//     0 0 | 1
+//     0 1 | 1
+//     ...
+fn write_truth_table<W: Write>(f : &Formula, out : &mut W) -> io::Result<()>
+{
+    write_table(f, out, " ", " | ", &f.to_string())
+}
+
+// The truth table as CSV: a header of the atom names plus a `result` column, and 0/1 cells.
+fn truth_table_csv(f : &Formula) -> String
+{
+    let mut buf = Vec::<u8>::new();
+    write_table(f, &mut buf, ",", ",", "result").expect("writing to a Vec cannot fail");
+    String::from_utf8(buf).expect("truth table output is valid UTF-8")
+}
+
+// Shared row writer for the truth table formats.  `sep` goes between atom columns, `result_sep` before
+// the result column.
+fn write_table<W: Write>(f : &Formula, out : &mut W, sep : &str, result_sep : &str, result_name : &str) -> io::Result<()>
+{
+    let atoms = atoms(f);
+    let names : Vec<String> = atoms.iter().map(|c| c.to_string()).collect();
+    writeln!(out, "{}{}{}", names.join(sep), result_sep, result_name)?;
+
+    for row in 0..(1u64 << atoms.len())
+    {
+        let env = assignment(&atoms, row);
+        let cells : Vec<&str> = atoms.iter().map(|c| if env[c] { "1" } else { "0" }).collect();
+        writeln!(out, "{}{}{}", cells.join(sep), result_sep, if eval(f, &env) { 1 } else { 0 })?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Polarity {
+    Positive,
+    Negative,
+    Both,
+}
+
+// How each atom occurs once the formula is in NNF: only plain, only negated, or both.  Implications and
+// biconditionals are expanded first, so the atoms of an `Iff` always come out as `Both`.
+fn atom_polarities(f : &Formula) -> HashMap<char, Polarity>
+{
+    fn walk(f : &Formula, polarities : &mut HashMap<char, Polarity>)
+    {
+        let (c, p) = match f
+        {
+            &Formula::Atom(c) => (c, Polarity::Positive),
+            &Formula::Not(box Formula::Atom(c)) => (c, Polarity::Negative),
+            &Formula::And(ref v) | &Formula::Or(ref v) => { for x in v { walk(x, polarities); } return; }
+            &Formula::True | &Formula::False => return,
+            _ => unreachable!("formula is in negation normal form"),
+        };
+        let merged = match polarities.get(&c)
+        {
+            Some(&q) if q != p => Polarity::Both,
+            _ => p,
+        };
+        polarities.insert(c, merged);
+    }
+
+    let mut polarities = HashMap::new();
+    walk(&to_nnf(f.clone()), &mut polarities);
+    polarities
+}
+
+// Normalization.  `normalize` repeats the following passes, in this order, until the formula stops
+// changing:
+//
+//   1. flatten: an AND directly inside an AND (or OR inside OR) is merged into its parent;
+//   2. fold constants: TRUE and FALSE are propagated through every connective, an OR containing some
+//      child and its negation becomes TRUE (an AND, FALSE), and an AND or OR left with a single child is
+//      replaced by that child (with none, by its identity);
+//   3. dedup and absorb: repeated children of an AND/OR are dropped, as are absorbed ones, i.e.
+//      P OR (P AND Q) ~> P, and an AND whose conjuncts include all those of a sibling;
+//   4. sort: the children of AND, OR and IFF(...) and the two sides of <-> are put in `Ord` order.
+//
+// Each pass works bottom-up over the whole formula.  Formulas that are equal up to associativity and
+// commutativity normalize to the identical value, and normalizing twice changes nothing.
+
+fn normalize(f : Formula) -> Formula
+{
+    let mut f = f;
+    loop
+    {
+        let g = sort_children(dedup_absorb(fold_constants(flatten(f.clone()))));
+        if g == f {
+            return g;
+        }
+        f = g;
+    }
+}
+
+// A cleanup that keeps the formula close to how it was written: `normalize` without its sorting pass, so
+// operands stay in their original order, and like it leaving ->, <-> and IFF(...) in place rather than
+// expanding them as `simplify` does.
+fn simplify_preserve_implications(f : Formula) -> Formula
+{
+    let mut f = f;
+    loop
+    {
+        let g = dedup_absorb(fold_constants(flatten(f.clone())));
+        if g == f {
+            return g;
+        }
+        f = g;
+    }
+}
+
+// Apply `pass` to the children of `f`, keeping the connective.
+fn map_children<F: Fn(Formula) -> Formula>(f : Formula, pass : F) -> Formula
+{
+    match f
+    {
+        g @ Formula::True | g @ Formula::False | g @ Formula::Atom(_) => g,
+        Formula::Not(n) => Formula::Not(box pass(*n)),
+        Formula::Implies { l, r } => Formula::Implies { l: box pass(*l), r: box pass(*r) },
+        Formula::Iff { l, r } => Formula::Iff { l: box pass(*l), r: box pass(*r) },
+        Formula::IffAll(v) => Formula::IffAll(v.into_iter().map(pass).collect()),
+        Formula::And(v) => Formula::And(v.into_iter().map(pass).collect()),
+        Formula::Or(v) => Formula::Or(v.into_iter().map(pass).collect()),
+        Formula::Annotated { note, inner } => Formula::Annotated { note: note, inner: box pass(*inner) },
+    }
+}
+
+// `f` with every annotation removed.
+fn strip_annotations(f : Formula) -> Formula
+{
+    match f
+    {
+        Formula::Annotated { inner, .. } => strip_annotations(*inner),
+        g @ _ => map_children(g, strip_annotations),
+    }
+}
+
+fn flatten(f : Formula) -> Formula
+{
+    match map_children(f, flatten)
+    {
+        Formula::And(v) => {
+            let mut flat = Vec::new();
+            for x in v {
+                match x { Formula::And(w) => flat.extend(w), g @ _ => flat.push(g) }
+            }
+            Formula::And(flat)
+        }
+        Formula::Or(v) => {
+            let mut flat = Vec::new();
+            for x in v {
+                match x { Formula::Or(w) => flat.extend(w), g @ _ => flat.push(g) }
+            }
+            Formula::Or(flat)
+        }
+        g @ _ => g,
+    }
+}
+
+// ~g, folding a constant.
+fn not(g : Formula) -> Formula
+{
+    match g
+    {
+        Formula::True => Formula::False,
+        Formula::False => Formula::True,
+        g @ _ => Formula::Not(box g),
+    }
+}
+
+// A conjunction of `v`, or its only member, or TRUE if there is none.
+fn and_of(mut v : Vec<Formula>) -> Formula
+{
+    match v.len()
+    {
+        0 => Formula::True,
+        1 => v.pop().unwrap(),
+        _ => Formula::And(v),
+    }
+}
+
+fn or_of(mut v : Vec<Formula>) -> Formula
+{
+    match v.len()
+    {
+        0 => Formula::False,
+        1 => v.pop().unwrap(),
+        _ => Formula::Or(v),
+    }
+}
+
+fn fold_constants(f : Formula) -> Formula
+{
+    match map_children(f, fold_constants)
+    {
+        Formula::Not(n) => not(*n),
+        // Let the parent see the constant.
+        Formula::Annotated { inner: box Formula::True, .. } => Formula::True,
+        Formula::Annotated { inner: box Formula::False, .. } => Formula::False,
+        Formula::And(v) => {
+            if v.contains(&Formula::False) || has_complement(&v) {
+                return Formula::False;
+            }
+            and_of(v.into_iter().filter(|x| *x != Formula::True).collect())
+        }
+        Formula::Or(v) => {
+            if v.contains(&Formula::True) || has_complement(&v) {
+                return Formula::True;
+            }
+            or_of(v.into_iter().filter(|x| *x != Formula::False).collect())
+        }
+        Formula::Implies { l, r } => match (*l, *r)
+        {
+            (Formula::True, r) => r,
+            (Formula::False, _) | (_, Formula::True) => Formula::True,
+            (l, Formula::False) => not(l),
+            (l, r) => Formula::Implies { l: box l, r: box r },
+        },
+        Formula::Iff { l, r } => match (*l, *r)
+        {
+            (Formula::True, x) | (x, Formula::True) => x,
+            (Formula::False, x) | (x, Formula::False) => not(x),
+            (l, r) => Formula::Iff { l: box l, r: box r },
+        },
+        Formula::IffAll(v) => {
+            let has_true = v.contains(&Formula::True);
+            let has_false = v.contains(&Formula::False);
+            let rest : Vec<Formula> = v.into_iter().filter(|x| *x != Formula::True && *x != Formula::False).collect();
+            match (has_true, has_false)
+            {
+                (true, true) => Formula::False,
+                (true, false) => fold_constants(Formula::And(rest)),
+                (false, true) => fold_constants(Formula::And(rest.into_iter().map(not).collect())),
+                (false, false) if rest.len() <= 1 => Formula::True,
+                (false, false) => Formula::IffAll(rest),
+            }
+        }
+        g @ _ => g,
+    }
+}
+
+// True if some member of `v` also appears negated in `v`.
+fn has_complement(v : &[Formula]) -> bool
+{
+    v.iter().any(|x| match x {
+        &Formula::Not(ref n) => v.contains(n),
+        _ => false,
+    })
+}
+
+fn dedup_absorb(f : Formula) -> Formula
+{
+    // Drop repeats, then drop any child whose `inner` children (as produced by the opposite connective)
+    // include a sibling, or all of a sibling's.
+    fn absorb(v : Vec<Formula>, inner : fn(&Formula) -> Option<&Vec<Formula>>) -> Vec<Formula>
+    {
+        let mut unique : Vec<Formula> = Vec::new();
+        for x in v {
+            if !unique.contains(&x) { unique.push(x); }
+        }
+
+        let absorbed = |i : usize| match inner(&unique[i])
+        {
+            None => false,
+            Some(ds) => unique.iter().enumerate().any(|(j, c)| j != i && match inner(c) {
+                Some(cs) => cs.len() < ds.len() && cs.iter().all(|x| ds.contains(x)),
+                None => ds.contains(c),
+            }),
+        };
+        let keep : Vec<bool> = (0..unique.len()).map(|i| !absorbed(i)).collect();
+        unique.into_iter().zip(keep).filter(|p| p.1).map(|p| p.0).collect()
+    }
+
+    fn ors(f : &Formula) -> Option<&Vec<Formula>> { match f { &Formula::Or(ref v) => Some(v), _ => None } }
+    fn ands(f : &Formula) -> Option<&Vec<Formula>> { match f { &Formula::And(ref v) => Some(v), _ => None } }
+
+    match map_children(f, dedup_absorb)
+    {
+        Formula::And(v) => Formula::And(absorb(v, ors)),
+        Formula::Or(v) => Formula::Or(absorb(v, ands)),
+        g @ _ => g,
+    }
+}
+
+fn sort_children(f : Formula) -> Formula
+{
+    match map_children(f, sort_children)
+    {
+        Formula::And(mut v) => { v.sort(); Formula::And(v) }
+        Formula::Or(mut v) => { v.sort(); Formula::Or(v) }
+        Formula::IffAll(mut v) => { v.sort(); Formula::IffAll(v) }
+        Formula::Iff { l, r } => if r < l { Formula::Iff { l: r, r: l } } else { Formula::Iff { l: l, r: r } },
+        g @ _ => g,
+    }
+}
+
+// CNF inspection.  A literal is an atom or a negated atom, a clause is a literal or a disjunction of
+// literals, and a formula is in CNF if it is a clause or a conjunction of clauses.
+
+fn is_literal(f : &Formula) -> bool
+{
+    match f
+    {
+        &Formula::Atom(_) | &Formula::Not(box Formula::Atom(_)) => true,
+        _ => false,
+    }
+}
+
+fn is_clause(f : &Formula) -> bool
+{
+    match f
+    {
+        &Formula::Or(ref v) => v.iter().all(is_literal),
+        _ => is_literal(f),
+    }
+}
+
+// The literals of each clause of a CNF formula, borrowed in order, or None if `f` is not in CNF.  A bare
+// literal (as a clause or as the whole formula) comes back as a one-element slice.
+fn clauses(f : &Formula) -> Option<impl Iterator<Item = &[Formula]>>
+{
+    let conjuncts = match f
+    {
+        &Formula::And(ref v) => &v[..],
+        _ => std::slice::from_ref(f),
+    };
+    if !conjuncts.iter().all(is_clause) {
+        return None;
+    }
+    Some(conjuncts.iter().map(|c| match c {
+        &Formula::Or(ref v) => &v[..],
+        _ => std::slice::from_ref(c),
+    }))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+struct Literal
+{
+    atom: char,
+    negated: bool,
+}
+
+impl Literal
+{
+    fn negate(self) -> Literal
+    {
+        Literal { atom: self.atom, negated: !self.negated }
+    }
+}
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.negated { write!(f, "~{}", self.atom) } else { write!(f, "{}", self.atom) }
+    }
+}
+
+fn literal_of(f : &Formula) -> Option<Literal>
+{
+    match f
+    {
+        &Formula::Atom(c) => Some(Literal { atom: c, negated: false }),
+        &Formula::Not(box Formula::Atom(c)) => Some(Literal { atom: c, negated: true }),
+        _ => None,
+    }
+}
+
+fn literal_formula(l : Literal) -> Formula
+{
+    if l.negated { Formula::Not(box Formula::Atom(l.atom)) } else { Formula::Atom(l.atom) }
+}
+
+// The formula of a clause list: an AND of ORs of literals, using a bare literal for a unit clause, FALSE
+// for the empty clause and TRUE for no clauses.
+fn cnf_formula(clauses : &[Vec<Literal>]) -> Formula
+{
+    and_of(clauses.iter().map(|c| or_of(c.iter().map(|&l| literal_formula(l)).collect())).collect())
+}
+
+// `f` as rules: each clause of its CNF, in order, becomes (A1 AND ... AND Am) -> (B1 OR ... OR Bn) for its
+// negative literals ~Ai and positive literals Bj, so ~A OR ~B OR C is (A AND B) -> C.  A clause with no
+// negative literals stays a plain disjunction (a fact), and one with no positive literals has consequent
+// FALSE (a constraint).  Tautologous clauses are dropped.
+fn to_implication_normal_form(f : Formula) -> Formula
+{
+    let rule = |c : Vec<Literal>| {
+        let antecedent : Vec<Formula> = c.iter().filter(|l| l.negated).map(|l| Formula::Atom(l.atom)).collect();
+        let consequent = or_of(c.iter().filter(|l| !l.negated).map(|l| Formula::Atom(l.atom)).collect());
+        if antecedent.is_empty() {
+            consequent
+        } else {
+            Formula::Implies { l: box and_of(antecedent), r: box consequent }
+        }
+    };
+    and_of(to_clauses(&f).iter().filter_map(|c| normalize_clause(c)).map(rule).collect())
+}
+
+// Convert any formula to a list of clauses by distributing OR over AND in its NNF.  The top-level
+// conjuncts are converted one at a time, and if a unit clause arrives whose complement has already been
+// seen the conversion stops there and returns just the empty clause, skipping the remaining (possibly
+// expensive) conjuncts.
+fn to_clauses(f : &Formula) -> Vec<Vec<Literal>>
+{
+    let conjuncts = match to_nnf(f.clone())
+    {
+        Formula::And(v) => v,
+        g @ _ => vec!(g),
+    };
+
+    let mut clauses = Vec::new();
+    let mut units = BTreeSet::new();
+    for c in &conjuncts
+    {
+        for clause in nnf_clauses(c)
+        {
+            match clause.len()
+            {
+                0 => return vec!(vec!()),
+                1 if units.contains(&clause[0].negate()) => return vec!(vec!()),
+                1 => { units.insert(clause[0]); }
+                _ => {}
+            }
+            clauses.push(clause);
+        }
+    }
+    clauses
+}
+
+fn nnf_clauses(f : &Formula) -> Vec<Vec<Literal>>
+{
+    nnf_clauses_within(f, usize::MAX).expect("an unlimited budget is never exceeded")
+}
+
+// The clauses of an NNF formula, or None as soon as they would hold more than `budget` literals in total.
+fn nnf_clauses_within(f : &Formula, budget : usize) -> Option<Vec<Vec<Literal>>>
+{
+    let clauses = match f
+    {
+        &Formula::True => vec!(),
+        &Formula::False => vec!(vec!()),
+        &Formula::And(ref v) => {
+            let mut clauses = Vec::new();
+            for x in v {
+                clauses.extend(nnf_clauses_within(x, budget)?);
+                if literal_count(&clauses) > budget {
+                    return None;
+                }
+            }
+            clauses
+        }
+
+        // (A1 ^ A2) v (B1 ^ B2) ~> (A1 v B1) ^ (A1 v B2) ^ (A2 v B1) ^ (A2 v B2)
+        &Formula::Or(ref v) => {
+            let mut acc = vec!(vec!());
+            for x in v {
+                let xs = nnf_clauses_within(x, budget)?;
+                let mut product = Vec::new();
+                for a in &acc {
+                    for b in &xs {
+                        let mut c : Vec<Literal> = a.clone();
+                        c.extend(b.iter().cloned());
+                        product.push(c);
+                    }
+                }
+                if literal_count(&product) > budget {
+                    return None;
+                }
+                acc = product;
+            }
+            acc
+        }
+
+        _ => vec!(vec!(literal_of(f).expect("formula is not in negation normal form"))),
+    };
+    if literal_count(&clauses) > budget { None } else { Some(clauses) }
+}
+
+// The number of clauses that distributing OR over AND in the NNF of `f` produces (as `nnf_clauses` does,
+// before any clause is merged or dropped), computed without building them.  An AND has the sum of its
+// children's counts and an OR their product; as in `nnf_size_estimate`, each node also gets the count for
+// its negation.  Saturates at `u128::MAX`.
+fn cnf_clause_count_estimate(f : &Formula) -> u128
+{
+    let sum = |v : &[u128]| v.iter().fold(0u128, |a, &b| a.saturating_add(b));
+    let product = |v : &[u128]| v.iter().fold(1u128, |a, &b| a.saturating_mul(b));
+    let (positive, _) = fold(f, &mut |g, c : Vec<(u128, u128)>| {
+        let p : Vec<u128> = c.iter().map(|x| x.0).collect();
+        let n : Vec<u128> = c.iter().map(|x| x.1).collect();
+        match g
+        {
+            &Formula::True => (0, 1),
+            &Formula::False => (1, 0),
+            &Formula::Atom(_) => (1, 1),
+            &Formula::Not(_) => (n[0], p[0]),
+            &Formula::Annotated { .. } => (p[0], n[0]),
+            &Formula::And(_) => (sum(&p), product(&n)),
+            &Formula::Or(_) => (product(&p), sum(&n)),
+            // l -> r ~> ~l v r, and its negation l ^ ~r
+            &Formula::Implies { .. } => (n[0].saturating_mul(p[1]), p[0].saturating_add(n[1])),
+            // l <-> r ~> (~l v r) ^ (~r v l), and its negation (l ^ ~r) v (r ^ ~l)
+            &Formula::Iff { .. } => (n[0].saturating_mul(p[1]).saturating_add(n[1].saturating_mul(p[0])), p[0].saturating_add(n[1]).saturating_mul(p[1].saturating_add(n[0]))),
+            // IFF(x1, ..., xn) ~> (x1 ^ ... ^ xn) v (~x1 ^ ... ^ ~xn), and its negation
+            // (~x1 v ... v ~xn) ^ (x1 v ... v xn)
+            &Formula::IffAll(_) => (sum(&p).saturating_mul(sum(&n)), product(&n).saturating_add(product(&p))),
+        }
+    });
+    positive
+}
+
+// The number of literals in the longest term that distributing AND over OR in the NNF of `f` produces
+// (before any term is merged or dropped), computed without building them: the dual of
+// `cnf_clause_count_estimate`.  An AND's terms join one term of each child, so its widest is the sum of
+// its children's widest, and an OR's widest is the widest of any child.  Each node also gets the width for
+// its negation, and None stands for having no terms at all (as for FALSE), which an AND passes on.  0 if
+// there are no terms.
+fn dnf_max_term_size_estimate(f : &Formula) -> usize
+{
+    let sum = |v : &[Option<usize>]| v.iter().fold(Some(0), |a, &b| Some(a? + b?));
+    let max = |v : &[Option<usize>]| v.iter().cloned().max().unwrap_or(None);
+    let (positive, _) = fold(f, &mut |g, c : Vec<(Option<usize>, Option<usize>)>| {
+        let p : Vec<Option<usize>> = c.iter().map(|x| x.0).collect();
+        let n : Vec<Option<usize>> = c.iter().map(|x| x.1).collect();
+        match g
+        {
+            &Formula::True => (Some(0), None),
+            &Formula::False => (None, Some(0)),
+            &Formula::Atom(_) => (Some(1), Some(1)),
+            &Formula::Not(_) => (n[0], p[0]),
+            &Formula::Annotated { .. } => (p[0], n[0]),
+            &Formula::And(_) => (sum(&p), max(&n)),
+            &Formula::Or(_) => (max(&p), sum(&n)),
+            // l -> r ~> ~l v r, and its negation l ^ ~r
+            &Formula::Implies { .. } => (max(&[n[0], p[1]]), sum(&[p[0], n[1]])),
+            // l <-> r ~> (~l v r) ^ (~r v l), and its negation (l ^ ~r) v (r ^ ~l)
+            &Formula::Iff { .. } => (sum(&[max(&[n[0], p[1]]), max(&[n[1], p[0]])]), max(&[sum(&[p[0], n[1]]), sum(&[p[1], n[0]])])),
+            // IFF(x1, ..., xn) ~> (x1 ^ ... ^ xn) v (~x1 ^ ... ^ ~xn), and its negation
+            // (~x1 v ... v ~xn) ^ (x1 v ... v xn)
+            &Formula::IffAll(_) => (max(&[sum(&p), sum(&n)]), sum(&[max(&n), max(&p)])),
+        }
+    });
+    positive.unwrap_or(0)
+}
+
+fn literal_count(clauses : &[Vec<Literal>]) -> usize
+{
+    clauses.iter().map(|c| c.len()).sum()
+}
+
+// The formula of a term list: an OR of ANDs of literals, the dual of `cnf_formula`.
+fn dnf_formula(terms : &[Vec<Literal>]) -> Formula
+{
+    or_of(terms.iter().map(|t| and_of(t.iter().map(|&l| literal_formula(l)).collect())).collect())
+}
+
+// The largest normal form, in literals, that `to_smallest_normal_form` will build while looking for a
+// smaller one.
+const NORMAL_FORM_BUDGET : usize = 10_000;
+
+#[derive(Clone, PartialEq, Debug)]
+enum NormalForm {
+    Cnf(Formula),
+    Dnf(Formula),
+}
+
+// Whichever of a CNF and a DNF of `f` has fewer literals, preferring CNF on a tie, or None if neither fits
+// in `NORMAL_FORM_BUDGET`.  Each is abandoned as soon as it grows past the budget.
+fn to_smallest_normal_form(f : &Formula) -> Option<NormalForm>
+{
+    // Tautological clauses and terms are dropped and repeated literals merged before comparing sizes.
+    fn tidy(clauses : Vec<Vec<Literal>>) -> Vec<Vec<Literal>>
+    {
+        clauses.iter().filter_map(|c| normalize_clause(c)).collect()
+    }
+
+    let cnf = nnf_clauses_within(&to_nnf(f.clone()), NORMAL_FORM_BUDGET).map(tidy);
+    // A DNF of f is the negation of a CNF of ~f.  Its budget cannot be cut to the size of the CNF, as
+    // tidying may shrink it by more than it does the CNF.
+    let dnf = nnf_clauses_within(&negate(f.clone()), NORMAL_FORM_BUDGET).map(tidy)
+        .map(|terms| terms.into_iter().map(|t| t.into_iter().map(Literal::negate).collect()).collect::<Vec<Vec<Literal>>>());
+
+    match (cnf, dnf)
+    {
+        (ref cnf, Some(ref terms)) if cnf.as_ref().map_or(true, |c| literal_count(terms) < literal_count(c)) => Some(NormalForm::Dnf(dnf_formula(terms))),
+        (Some(clauses), _) => Some(NormalForm::Cnf(cnf_formula(&clauses))),
+        (None, _) => None,
+    }
+}
+
+// True if the clause set is unsatisfiable for an obvious reason: it contains the empty clause, or two
+// complementary unit clauses P and ~P.
+fn trivially_unsat(clauses : &[Vec<Literal>]) -> bool
+{
+    let mut units = BTreeSet::new();
+    for c in clauses
+    {
+        match c.len()
+        {
+            0 => return true,
+            1 => {
+                if units.contains(&c[0].negate()) {
+                    return true;
+                }
+                units.insert(c[0]);
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+// Preprocess a clause set by deriving unit clauses and propagating them, to a fixpoint.  A unit comes
+// either from a clause that propagation has cut down to one literal, or from resolving two binary clauses
+// x OR y and x OR ~y into x (resolvents of longer clauses are never units).  Propagating a unit drops the
+// other clauses it satisfies and removes its complement from the rest.  The result is equivalent to the
+// input, with each clause normalized and tautologies dropped, and is just the empty clause if a
+// contradiction turns up.
+fn derive_units(clauses : Vec<Vec<Literal>>) -> Vec<Vec<Literal>>
+{
+    let mut clauses : Vec<Vec<Literal>> = clauses.iter().filter_map(|c| normalize_clause(c)).collect();
+    loop
+    {
+        let mut units : BTreeSet<Literal> = clauses.iter().filter(|c| c.len() == 1).map(|c| c[0]).collect();
+        let binary : BTreeSet<&Vec<Literal>> = clauses.iter().filter(|c| c.len() == 2).collect();
+        for c in &binary {
+            for (x, y) in vec!((c[0], c[1]), (c[1], c[0])) {
+                if let Some(other) = normalize_clause(&[x, y.negate()]) {
+                    if binary.contains(&other) {
+                        units.insert(x);
+                    }
+                }
+            }
+        }
+        if units.iter().any(|u| units.contains(&u.negate())) {
+            return vec!(vec!());
+        }
+
+        let mut next : Vec<Vec<Literal>> = units.iter().map(|&u| vec!(u)).collect();
+        for c in &clauses
+        {
+            if c.iter().any(|l| units.contains(l)) {
+                continue;
+            }
+            let rest : Vec<Literal> = c.iter().cloned().filter(|l| !units.contains(&l.negate())).collect();
+            if rest.is_empty() {
+                return vec!(vec!());
+            }
+            next.push(rest);
+        }
+        next.sort();
+        next.dedup();
+        let mut previous = clauses.clone();
+        previous.sort();
+        previous.dedup();
+        if next == previous {
+            return next;
+        }
+        clauses = next;
+    }
+}
+
+// The indices of the clauses that a partial assignment falsifies: those whose every literal is assigned
+// and false.  A clause with an unassigned literal is not counted, however the rest of it is assigned.
+fn falsified_clauses(clauses : &[Vec<Literal>], assignment : &HashMap<char, bool>) -> Vec<usize>
+{
+    (0..clauses.len()).filter(|&i| clauses[i].iter().all(|l| assignment.get(&l.atom).map_or(false, |&v| v == l.negated))).collect()
+}
+
+// A clause with its literals sorted and repeats removed, or None if it contains both P and ~P.
+fn normalize_clause(c : &[Literal]) -> Option<Vec<Literal>>
+{
+    let mut c = c.to_vec();
+    c.sort();
+    c.dedup();
+    if c.windows(2).any(|w| w[0].atom == w[1].atom) { None } else { Some(c) }
+}
+
+// The clauses of `f` in canonical form: each clause sorted with repeats removed, tautologies dropped, and
+// the clause list sorted with repeats removed.  Two formulas whose CNFs differ only in those respects get
+// the same list.
+fn canonical_clauses(f : &Formula) -> Vec<Vec<Literal>>
+{
+    let mut clauses : Vec<Vec<Literal>> = to_clauses(f).iter().filter_map(|c| normalize_clause(c)).collect();
+    clauses.sort();
+    clauses.dedup();
+    clauses
+}
+
+// A hash of a clause set that ignores the order of the clauses, the order of the literals within them, and
+// repeats of either, for use as a cache key for solver results.  Clause sets with the same fingerprint are
+// almost certainly the same set.  The value can change between Rust releases, so it should not be stored.
+fn clause_set_fingerprint(clauses : &[Vec<Literal>]) -> u64
+{
+    let mut canonical : Vec<Vec<Literal>> = clauses.iter().map(|c| {
+        let mut c = c.clone();
+        c.sort();
+        c.dedup();
+        c
+    }).collect();
+    canonical.sort();
+    canonical.dedup();
+
+    let mut hasher = DefaultHasher::new();
+    canonical.hash(&mut hasher);
+    hasher.finish()
+}
+
+// True if every literal of `a` is in `b`; both must be sorted.
+fn subsumes(a : &[Literal], b : &[Literal]) -> bool
+{
+    a.len() <= b.len() && a.iter().all(|l| b.binary_search(l).is_ok())
+}
+
+// The prime implicates of `f`: the clauses it entails that stop being entailed if any literal is
+// removed.  Starting from the CNF clauses, resolvents are added until no new unsubsumed one appears,
+// removing the clauses each newcomer subsumes as it goes (the dual of Blake's iterated consensus).
+fn prime_implicates(f : &Formula) -> Vec<Vec<Literal>>
+{
+    let mut set : Vec<Vec<Literal>> = Vec::new();
+    for c in to_clauses(f).iter().filter_map(|c| normalize_clause(c))
+    {
+        if !set.iter().any(|s| subsumes(s, &c)) {
+            set.retain(|s| !subsumes(&c, s));
+            set.push(c);
+        }
+    }
+
+    loop
+    {
+        let mut found = None;
+        'search: for i in 0..set.len() {
+            for j in (i + 1)..set.len() {
+                for l in &set[i] {
+                    if set[j].binary_search(&l.negate()).is_err() { continue; }
+                    let mut r : Vec<Literal> = set[i].iter().chain(set[j].iter()).cloned().filter(|x| x.atom != l.atom).collect();
+                    r.sort();
+                    r.dedup();
+                    if normalize_clause(&r).is_some() && !set.iter().any(|s| subsumes(s, &r)) {
+                        found = Some(r);
+                        break 'search;
+                    }
+                }
+            }
+        }
+        match found
+        {
+            None => break,
+            Some(r) => {
+                set.retain(|s| !subsumes(&r, s));
+                set.push(r);
+            }
+        }
+    }
+    set.sort();
+    set
+}
+
+// The indices of a cheapest set of candidates covering each of `items` items, where `covers[i][k]` says
+// candidate `i` covers item `k` and a set costs the sum of its candidates' `cost`s, compared lexicographically.  Solved
+// exactly by branch and bound.  Panics if some item has no candidate.
+fn smallest_cover(items : usize, covers : &[Vec<bool>], cost : &[(usize, usize)]) -> Vec<usize>
+{
+    fn search(covers : &[Vec<bool>], cost : &[(usize, usize)], chosen : &mut Vec<usize>, covered : &mut Vec<usize>, best : &mut Option<(usize, usize, Vec<usize>)>)
+    {
+        let total = chosen.iter().fold((0, 0), |acc, &i| (acc.0 + cost[i].0, acc.1 + cost[i].1));
+        if best.as_ref().map_or(false, |b| (b.0, b.1) <= total) {
+            return;
+        }
+        // Branch on the uncovered item with the fewest candidates covering it.
+        let item = (0..covered.len()).filter(|&k| covered[k] == 0).min_by_key(|&k| covers.iter().filter(|c| c[k]).count());
+        match item
+        {
+            None => *best = Some((total.0, total.1, chosen.clone())),
+            Some(k) => {
+                for i in (0..covers.len()).filter(|&i| covers[i][k]) {
+                    chosen.push(i);
+                    for (n, &c) in covered.iter_mut().zip(&covers[i]) { if c { *n += 1; } }
+                    search(covers, cost, chosen, covered, best);
+                    for (n, &c) in covered.iter_mut().zip(&covers[i]) { if c { *n -= 1; } }
+                    chosen.pop();
+                }
+            }
+        }
+    }
+
+    let mut best = None;
+    search(covers, cost, &mut Vec::new(), &mut vec!(0; items), &mut best);
+    let (_, _, chosen) = best.expect("smallest_cover: some item cannot be covered");
+    chosen
+}
+
+// A smallest CNF equivalent to `f`: as few clauses as possible, and among those as few literals.  Every
+// such CNF can be made of prime implicates, and a set of them is equivalent to `f` exactly when each
+// assignment falsifying `f` falsifies one of the chosen clauses, so this is a set cover of the false rows
+// of the truth table.
+fn to_minimal_cnf(f : &Formula) -> Formula
+{
+    let atoms = atoms(f);
+    let implicates = prime_implicates(f);
+    let false_rows : Vec<HashMap<char, bool>> = (0..(1u64 << atoms.len())).map(|row| assignment(&atoms, row)).filter(|env| !eval(f, env)).collect();
+
+    // covers[i][k]: clause i is false on false row k.
+    let covers : Vec<Vec<bool>> = implicates.iter().map(|c| {
+        false_rows.iter().map(|env| c.iter().all(|l| env[&l.atom] == l.negated)).collect()
+    }).collect();
+
+    let cost : Vec<(usize, usize)> = implicates.iter().map(|c| (1, c.len())).collect();
+    let chosen = smallest_cover(false_rows.len(), &covers, &cost);
+    let clauses : Vec<Vec<Literal>> = chosen.into_iter().map(|i| implicates[i].clone()).collect();
+    cnf_formula(&clauses)
+}
+
+// The gate an auxiliary atom of the Tseitin encoding stands for, over the literals of its operands.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+enum GateDef {
+    And(Vec<Literal>),
+    Or(Vec<Literal>),
+    Implies(Literal, Literal),
+    Iff(Literal, Literal),
+    IffAll(Vec<Literal>),
+}
+
+impl GateDef
+{
+    // The clauses saying that `out` is equal to the gate.
+    fn clauses(&self, out : char) -> Vec<Vec<Literal>>
+    {
+        let x = Literal { atom: out, negated: false };
+        let negated = |ls : &[Literal]| ls.iter().map(|l| l.negate()).collect::<Vec<Literal>>();
+        match *self
+        {
+            // x <-> l1 ^ ... ^ ln ~> (~x v l1) ^ ... ^ (~x v ln) ^ (x v ~l1 v ... v ~ln)
+            GateDef::And(ref ls) => {
+                let mut clauses : Vec<Vec<Literal>> = ls.iter().map(|&l| vec!(x.negate(), l)).collect();
+                clauses.push(Some(x).into_iter().chain(negated(ls)).collect());
+                clauses
+            }
+            // x <-> l1 v ... v ln ~> (x v ~l1) ^ ... ^ (x v ~ln) ^ (~x v l1 v ... v ln)
+            GateDef::Or(ref ls) => {
+                let mut clauses : Vec<Vec<Literal>> = ls.iter().map(|&l| vec!(x, l.negate())).collect();
+                clauses.push(Some(x.negate()).into_iter().chain(ls.iter().cloned()).collect());
+                clauses
+            }
+            GateDef::Implies(a, b) => GateDef::Or(vec!(a.negate(), b)).clauses(out),
+            GateDef::Iff(a, b) => vec!(
+                vec!(x.negate(), a.negate(), b),
+                vec!(x.negate(), a, b.negate()),
+                vec!(x, a, b),
+                vec!(x, a.negate(), b.negate())),
+            // x makes neighbouring arguments equal; ~x needs some argument false and some true.
+            GateDef::IffAll(ref ls) => {
+                let mut clauses = Vec::new();
+                for w in ls.windows(2) {
+                    clauses.push(vec!(x.negate(), w[0].negate(), w[1]));
+                    clauses.push(vec!(x.negate(), w[0], w[1].negate()));
+                }
+                clauses.push(Some(x).into_iter().chain(negated(ls)).collect());
+                clauses.push(Some(x).into_iter().chain(ls.iter().cloned()).collect());
+                clauses
+            }
+        }
+    }
+}
+
+// The Tseitin encoding of `f`: a fresh atom for every connective other than negation, defined equal to
+// its gate, with identical gates sharing one atom.  Constants are folded away first.  Returns the
+// definitions, operands before the gates that use them, and the literal equal to `f`, or the value of `f`
+// if it folds to a constant.
+fn tseitin(f : &Formula) -> (Vec<(char, GateDef)>, Result<Literal, bool>)
+{
+    fn define(g : &Formula, used : &mut BTreeSet<char>, defs : &mut Vec<(char, GateDef)>, known : &mut HashMap<GateDef, char>) -> Literal
+    {
+        let gate = match *g
+        {
+            Formula::Atom(c) => return Literal { atom: c, negated: false },
+            Formula::Not(ref n) => return define(n, used, defs, known).negate(),
+            Formula::Annotated { ref inner, .. } => return define(inner, used, defs, known),
+            Formula::True | Formula::False => unreachable!("constants are folded away"),
+            Formula::Implies { ref l, ref r } => GateDef::Implies(define(l, used, defs, known), define(r, used, defs, known)),
+            Formula::Iff { ref l, ref r } => GateDef::Iff(define(l, used, defs, known), define(r, used, defs, known)),
+            Formula::IffAll(ref v) => GateDef::IffAll(v.iter().map(|x| define(x, used, defs, known)).collect()),
+            Formula::And(ref v) => GateDef::And(v.iter().map(|x| define(x, used, defs, known)).collect()),
+            Formula::Or(ref v) => GateDef::Or(v.iter().map(|x| define(x, used, defs, known)).collect()),
+        };
+        if let Some(&c) = known.get(&gate) {
+            return Literal { atom: c, negated: false };
+        }
+        let c = fresh_atom(used);
+        known.insert(gate.clone(), c);
+        defs.push((c, gate));
+        Literal { atom: c, negated: false }
+    }
+
+    let f = fold_constants(f.clone());
+    match f
+    {
+        Formula::True => return (vec!(), Err(true)),
+        Formula::False => return (vec!(), Err(false)),
+        _ => {}
+    }
+    let mut used : BTreeSet<char> = atoms(&f).into_iter().collect();
+    let mut defs = Vec::new();
+    let root = define(&f, &mut used, &mut defs, &mut HashMap::new());
+    (defs, Ok(root))
+}
+
+// The auxiliary atoms of `to_cnf_tseitin(f)` and the gates they stand for, operands first.
+fn tseitin_definitions(f : &Formula) -> Vec<(char, GateDef)>
+{
+    tseitin(f).0
+}
+
+// A CNF of `f` with one auxiliary atom per gate (see `tseitin`), linear in the size of `f`.  It is not
+// equivalent to `f` but equisatisfiable with it, and its models restricted to the atoms of `f` are exactly
+// the models of `f`.
+fn to_cnf_tseitin(f : &Formula) -> Vec<Vec<Literal>>
+{
+    let (defs, root) = tseitin(f);
+    let mut clauses : Vec<Vec<Literal>> = defs.iter().flat_map(|&(c, ref gate)| gate.clauses(c)).collect();
+    match root
+    {
+        Ok(l) => clauses.push(vec!(l)),
+        Err(true) => {}
+        Err(false) => clauses.push(vec!()),
+    }
+    clauses
+}
+
+// A small integer standing for an atom name in a `Vocabulary`.  Ids are handed out densely from 0, so
+// they can index arrays.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+struct AtomId(u32);
+
+// A two-way map between atom names and `AtomId`s.  Interning a name that is already known returns its
+// existing id.
+#[derive(Clone, Default, Debug)]
+struct Vocabulary
+{
+    names: Vec<String>,
+    ids: HashMap<String, AtomId>,
+}
+
+impl Vocabulary
+{
+    fn new() -> Vocabulary
+    {
+        Vocabulary::default()
+    }
+
+    fn intern(&mut self, name : &str) -> AtomId
+    {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = AtomId(self.names.len() as u32);
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    // The id of a name that has been interned, without interning it.
+    fn get(&self, name : &str) -> Option<AtomId>
+    {
+        self.ids.get(name).cloned()
+    }
+
+    // Panics if `id` did not come from this vocabulary.
+    fn name(&self, id : AtomId) -> &str
+    {
+        &self.names[id.0 as usize]
+    }
+
+    fn len(&self) -> usize
+    {
+        self.names.len()
+    }
+}
+
+// The atom that stands for `id` in clauses read with a `Vocabulary`, e.g. by `parse_dimacs`.  These
+// start outside the Basic Multilingual Plane and stop short of the private use planes, so they clash
+// neither with atoms a user would write nor with `fresh_atom`'s.
+fn vocabulary_atom(id : AtomId) -> char
+{
+    checked_vocabulary_atom(id).expect("atom id out of range")
+}
+
+// `vocabulary_atom`, or None once the ids have run past the atoms set aside for them.
+fn checked_vocabulary_atom(id : AtomId) -> Option<char>
+{
+    id.0.checked_add(0x10000).and_then(std::char::from_u32).filter(|&c| c < '\u{F0000}')
+}
+
+// Read a CNF in DIMACS format a line at a time: `c` comment lines, a `p cnf <variables> <clauses>` header,
+// then clauses as whitespace-separated non-zero integers (-n for the negation of variable n), each ended
+// by 0, which may span lines or share them.  A line starting with `%` ends the input, as in the SATLIB
+// benchmarks.  Variable n is interned as the name "n" when a clause first mentions it, so the vocabulary
+// holds only the variables that occur, however many the header declares; its atom in the clauses is
+// `vocabulary_atom` of that id.  There are fewer than a million such atoms, and an input that mentions
+// more distinct variables is an error.
+//
+// Error spans are byte ranges in the whole input.
+fn parse_dimacs<R: BufRead>(reader : R) -> Result<(Vec<Vec<Literal>>, Vocabulary), ParseError>
+{
+    let mut vocabulary = Vocabulary::new();
+    let mut header : Option<(usize, usize)> = None;
+    let mut clauses = Vec::new();
+    let mut clause = Vec::new();
+    let mut offset = 0;
+    for line in reader.lines()
+    {
+        let line = line.map_err(|e| ParseError::new(&e.to_string(), offset..offset))?;
+        let start = offset;
+        offset += line.len() + 1;
+        let trimmed = line.trim_start();
+        let at = |token : &str| {
+            let i = start + (token.as_ptr() as usize - line.as_ptr() as usize);
+            i..i + token.len()
+        };
+        if trimmed.is_empty() || trimmed.starts_with('c') {
+            continue;
+        }
+        if trimmed.starts_with('%') {
+            break;
+        }
+        if trimmed.starts_with('p') {
+            let fields : Vec<&str> = trimmed.split_whitespace().collect();
+            let counts = match fields.as_slice()
+            {
+                &["p", "cnf", v, c] => v.parse().ok().zip(c.parse().ok()),
+                _ => None,
+            };
+            match counts
+            {
+                _ if header.is_some() => return Err(ParseError::new("a second 'p' line", at(trimmed))),
+                Some(counts) => header = Some(counts),
+                None => return Err(ParseError::new("expected 'p cnf <variables> <clauses>'", at(trimmed))),
+            }
+            continue;
+        }
+
+        let variables = match header
+        {
+            Some((v, _)) => v,
+            None => return Err(ParseError::new("clause before the 'p cnf' header", at(trimmed))),
+        };
+        for token in trimmed.split_whitespace()
+        {
+            let n : i64 = token.parse().map_err(|_| ParseError::new("expected an integer literal", at(token)))?;
+            if n == 0 {
+                clauses.push(mem::take(&mut clause));
+                continue;
+            }
+            if n.unsigned_abs() as usize > variables {
+                return Err(ParseError::new("variable beyond the number declared in the header", at(token)));
+            }
+            let name = n.abs().to_string();
+            let id = match vocabulary.get(&name)
+            {
+                Some(id) => id,
+                None if checked_vocabulary_atom(AtomId(vocabulary.len() as u32)).is_none() =>
+                    return Err(ParseError::new("too many distinct variables", at(token))),
+                None => vocabulary.intern(&name),
+            };
+            clause.push(Literal { atom: vocabulary_atom(id), negated: n < 0 });
+        }
+    }
+
+    let end = offset.saturating_sub(1);
+    if !clause.is_empty() {
+        return Err(ParseError::new("last clause not ended by 0", end..end));
+    }
+    match header
+    {
+        None => Err(ParseError::new("missing 'p cnf' header", end..end)),
+        Some((_, c)) if c != clauses.len() => Err(ParseError::new(&format!("header declares {} clauses but {} were found", c, clauses.len()), end..end)),
+        Some(_) => Ok((clauses, vocabulary)),
+    }
+}
+
+// A Craig interpolant of an unsatisfiable pair: a formula I over the atoms `a` and `b` share such that `a`
+// entails I and I AND `b` is unsatisfiable.  None if `a AND b` is satisfiable.
+//
+// The interpolant is built alongside a resolution refutation of the clauses of `a` and `b` (McMillan's
+// system): every clause carries a partial interpolant, which is the OR of its shared-atom literals for a
+// clause of `a` and TRUE for a clause of `b`.  Resolving on an atom that occurs only in `a` ORs the
+// parents' partial interpolants, and resolving on any other atom ANDs them.  The partial interpolant of the
+// empty clause is the result.
+fn interpolant(a : &Formula, b : &Formula) -> Option<Formula>
+{
+    let a_clauses : Vec<Vec<Literal>> = to_clauses(a).iter().filter_map(|c| normalize_clause(c)).collect();
+    let b_clauses : Vec<Vec<Literal>> = to_clauses(b).iter().filter_map(|c| normalize_clause(c)).collect();
+    let mut all = a_clauses.clone();
+    all.extend(b_clauses.iter().cloned());
+    if Solver::from_clauses(&all).solve() != SatResult::Unsat {
+        return None;
+    }
+
+    let in_b : BTreeSet<char> = b_clauses.iter().flat_map(|c| c.iter().map(|l| l.atom)).collect();
+    let mut set : Vec<(Vec<Literal>, Formula)> = Vec::new();
+    let labelled = a_clauses.into_iter().map(|c| {
+            let shared = c.iter().filter(|l| in_b.contains(&l.atom)).map(|&l| literal_formula(l)).collect();
+            (c, or_of(shared))
+        }).chain(b_clauses.into_iter().map(|c| (c, Formula::True)));
+    for (c, i) in labelled
+    {
+        if !set.iter().any(|s| subsumes(&s.0, &c)) {
+            set.retain(|s| !subsumes(&c, &s.0));
+            set.push((c, i));
+        }
+    }
+
+    loop
+    {
+        if let Some(&(_, ref i)) = set.iter().find(|s| s.0.is_empty()) {
+            return Some(normalize(i.clone()));
+        }
+        let mut found = None;
+        'search: for i in 0..set.len() {
+            for j in (i + 1)..set.len() {
+                for l in &set[i].0 {
+                    if set[j].0.binary_search(&l.negate()).is_err() { continue; }
+                    let mut r : Vec<Literal> = set[i].0.iter().chain(set[j].0.iter()).cloned().filter(|x| x.atom != l.atom).collect();
+                    r.sort();
+                    r.dedup();
+                    if normalize_clause(&r).is_some() && !set.iter().any(|s| subsumes(&s.0, &r)) {
+                        let parents = vec!(set[i].1.clone(), set[j].1.clone());
+                        let partial = if in_b.contains(&l.atom) { Formula::And(parents) } else { Formula::Or(parents) };
+                        found = Some((r, partial));
+                        break 'search;
+                    }
+                }
+            }
+        }
+        let (r, partial) = found.expect("resolution refutes an unsatisfiable clause set");
+        set.retain(|s| !subsumes(&r, &s.0));
+        set.push((r, partial));
+    }
+}
+
+// Search for a resolution refutation of the clauses of `f`, keeping only resolvents of at most
+// `max_clause_len` literals.  Some(true) if the empty clause is derived, so `f` is unsatisfiable;
+// Some(false) if the clauses saturate without any resolvent having been dropped, so `f` is satisfiable;
+// None if they saturate only because some were dropped.  The search always terminates, since there are
+// finitely many clauses of bounded length.
+//
+// Clauses are processed one at a time: each is resolved against every clause processed before it, unless
+// one of those subsumes it, and in turn removes the processed clauses it subsumes.
+fn resolution_refutation_bounded(f : &Formula, max_clause_len : usize) -> Option<bool>
+{
+    let mut queue : Vec<Vec<Literal>> = to_clauses(f).iter().filter_map(|c| normalize_clause(c)).collect();
+    queue.reverse();
+    let mut processed : Vec<Vec<Literal>> = Vec::new();
+    let mut dropped = false;
+    while let Some(c) = queue.pop()
+    {
+        if c.is_empty() {
+            return Some(true);
+        }
+        if processed.iter().any(|p| subsumes(p, &c)) {
+            continue;
+        }
+        processed.retain(|p| !subsumes(&c, p));
+        for p in &processed {
+            for l in &c {
+                if p.binary_search(&l.negate()).is_err() { continue; }
+                let r : Vec<Literal> = c.iter().chain(p.iter()).cloned().filter(|x| x.atom != l.atom).collect();
+                match normalize_clause(&r)
+                {
+                    Some(ref r) if r.len() > max_clause_len => dropped = true,
+                    Some(r) => queue.push(r),
+                    None => {}
+                }
+            }
+        }
+        processed.push(c);
+    }
+    if dropped { None } else { Some(false) }
+}
+
+// One step of a `ResolutionProof`: the clauses numbered `left` and `right` resolved on `pivot`, which is
+// positive in one of them and negative in the other, giving `resolvent`.
+#[derive(Clone, PartialEq, Debug)]
+struct ResolutionStep
+{
+    left: usize,
+    right: usize,
+    pivot: char,
+    resolvent: Vec<Literal>,
+}
+
+// A resolution refutation of a clause list.  The input clauses are numbered from 0 in order, and the
+// resolvent of each step gets the next number after them, so a step can use the input clauses and the
+// resolvents of earlier steps.
+#[derive(Clone, PartialEq, Debug)]
+struct ResolutionProof
+{
+    steps: Vec<ResolutionStep>,
+}
+
+// Why `check_resolution_proof` rejected a proof.  `step` is the index of the offending step.
+#[derive(Clone, PartialEq, Debug)]
+enum ProofError {
+    // A premise is not an input clause or the resolvent of an earlier step.
+    UnknownClause { step: usize, clause: usize },
+    // The pivot is not positive in one premise and negative in the other.
+    BadPivot { step: usize },
+    // The stated resolvent differs from the actual one, which is given.
+    WrongResolvent { step: usize, expected: Vec<Literal> },
+    // Neither an input clause nor the resolvent of any step is empty.
+    NoEmptyClause,
+}
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self
+        {
+            ProofError::UnknownClause { step, clause } => write!(f, "step {} uses clause {}, which is not available", step, clause),
+            ProofError::BadPivot { step } => write!(f, "step {} does not resolve on its pivot", step),
+            ProofError::WrongResolvent { step, ref expected } => {
+                write!(f, "step {} should derive {}", step, or_of(expected.iter().map(|&l| literal_formula(l)).collect()))
+            }
+            ProofError::NoEmptyClause => write!(f, "the proof does not derive the empty clause"),
+        }
+    }
+}
+
+// Check that `proof` is a resolution refutation of `clauses`: each step resolves two clauses available
+// to it on its pivot and states the resolvent correctly (as a set of literals, in any order), and the
+// empty clause is derived by some step (or is among `clauses`).  The first faulty step is reported.
+fn check_resolution_proof(clauses : &[Vec<Literal>], proof : &ResolutionProof) -> Result<(), ProofError>
+{
+    let set = |c : &[Literal]| { let mut c = c.to_vec(); c.sort(); c.dedup(); c };
+    let mut derived : Vec<Vec<Literal>> = clauses.iter().map(|c| set(c)).collect();
+    let mut refuted = derived.iter().any(|c| c.is_empty());
+    for (i, s) in proof.steps.iter().enumerate()
+    {
+        for &k in &[s.left, s.right] {
+            if k >= derived.len() {
+                return Err(ProofError::UnknownClause { step: i, clause: k });
+            }
+        }
+        let (a, b) = (&derived[s.left], &derived[s.right]);
+        let pos = Literal { atom: s.pivot, negated: false };
+        let (p, n) = if a.contains(&pos) { (a, b) } else { (b, a) };
+        if !p.contains(&pos) || !n.contains(&pos.negate()) {
+            return Err(ProofError::BadPivot { step: i });
+        }
+        let resolvent = set(&p.iter().filter(|&&l| l != pos).chain(n.iter().filter(|&&l| l != pos.negate())).cloned().collect::<Vec<_>>());
+        if set(&s.resolvent) != resolvent {
+            return Err(ProofError::WrongResolvent { step: i, expected: resolvent });
+        }
+        refuted |= resolvent.is_empty();
+        derived.push(resolvent);
+    }
+    if refuted { Ok(()) } else { Err(ProofError::NoEmptyClause) }
+}
+
+// The answer of `decide`, with evidence that can be checked without trusting the solver.
+#[derive(Clone, PartialEq, Debug)]
+enum Decision {
+    // A model assigning every atom of the formula, which `check_model` accepts.
+    Sat(HashMap<char, bool>),
+    // A refutation of the clauses `to_cnf_tseitin` gives for the formula, which `check_resolution_proof`
+    // accepts.  Those clauses are equisatisfiable with the formula.
+    Unsat(ResolutionProof),
+}
+
+// Whether `f` is satisfiable, with a certificate either way.  A model comes from the CDCL solver.  A
+// refutation comes from a separate DPLL search over the Tseitin clauses that records its search tree as a
+// tree-like resolution proof: when both values of the branching atom x lead to falsified clauses, one
+// containing ~x and the other x, their resolvent on x is falsified by the assignment above the branch.
+// Branching prefers an atom that some clause forces.  The proof can still be exponential in the number of
+// atoms, as tree-like resolution proofs sometimes must be.
+fn decide(f : &Formula) -> Decision
+{
+    let clauses = to_cnf_tseitin(f);
+    if let SatResult::Sat(m) = Solver::from_clauses(&clauses).solve() {
+        let atoms = atoms(f);
+        let mut model : HashMap<char, bool> = m.into_iter().filter(|&(c, _)| atoms.contains(&c)).collect();
+        for c in atoms {
+            model.entry(c).or_insert(false);
+        }
+        return Decision::Sat(model);
+    }
+
+    // The index of a clause of `derived` (the input clauses and the resolvents so far) that `assigned`
+    // falsifies, adding resolution steps to `steps` as needed.  `assigned` must falsify some clause
+    // whatever values the remaining atoms take.
+    fn refute(derived : &mut Vec<Vec<Literal>>, steps : &mut Vec<ResolutionStep>, inputs : usize, assigned : &mut HashMap<char, bool>) -> usize
+    {
+        let value = |l : &Literal, assigned : &HashMap<char, bool>| assigned.get(&l.atom).map(|&b| b != l.negated);
+        if let Some(i) = (0..inputs).find(|&i| derived[i].iter().all(|l| value(l, assigned) == Some(false))) {
+            return i;
+        }
+        // An unassigned literal of a clause that has no other, else of the first clause not yet satisfied.
+        let open : Vec<Vec<Literal>> = derived[..inputs].iter()
+            .filter(|c| !c.iter().any(|l| value(l, assigned) == Some(true)))
+            .map(|c| c.iter().cloned().filter(|l| value(l, assigned).is_none()).collect())
+            .collect();
+        let branch = open.iter().find(|c| c.len() == 1).or(open.first()).expect("a refuted assignment leaves a clause open")[0];
+
+        let mut falsified = Vec::new();
+        for &b in &[!branch.negated, branch.negated] {
+            assigned.insert(branch.atom, b);
+            let i = refute(derived, steps, inputs, assigned);
+            assigned.remove(&branch.atom);
+            if !derived[i].contains(&Literal { atom: branch.atom, negated: b }) {
+                // The clause does not depend on this branch.
+                return i;
+            }
+            falsified.push(i);
+        }
+        let (p, n) = (falsified[0], falsified[1]);
+        let mut resolvent : Vec<Literal> = derived[p].iter().chain(derived[n].iter()).cloned().filter(|l| l.atom != branch.atom).collect();
+        resolvent.sort();
+        resolvent.dedup();
+        steps.push(ResolutionStep { left: p, right: n, pivot: branch.atom, resolvent: resolvent.clone() });
+        derived.push(resolvent);
+        derived.len() - 1
+    }
+
+    let mut derived : Vec<Vec<Literal>> = clauses.clone();
+    let mut steps = Vec::new();
+    refute(&mut derived, &mut steps, clauses.len(), &mut HashMap::new());
+    Decision::Unsat(ResolutionProof { steps: steps })
+}
+
+// Parsing.  The syntax is the one `Display` produces: single-character atoms, `TRUE` and `FALSE`, `~` for
+// negation, `AND`, `OR`, `->` and `<->`, with parentheses for grouping.  The C-style (`!`, `&&`, `||`,
+// `true`, `false`) and Unicode (`¬`, `∧`, `∨`, `→`, `↔`, `⊤`, `⊥`) symbols of the `PrintStyle` presets
+// are accepted too.  From tightest to loosest binding the precedences are `~`, `AND`, `OR`, `->`, `<->`;
+// the last two associate to the right.  `~` applies to the single operand after it and needs no
+// parentheses, so `~P AND Q` is `(~P) AND Q`, and negations stack as in `~~P`.
+// Whitespace (including newlines), `// line` comments and `/* block */` comments may appear between any
+// tokens.
+//
+// `IFF(F1, ..., Fn)` says that all of its arguments have the same truth value.
+//
+// `let x := F in G` abbreviates G with F substituted for the atom x.  The body extends as far to the
+// right as possible, and the binding is expanded while parsing, so it never appears in the result.
+
+#[derive(Clone, PartialEq, Debug)]
+struct ParseError
+{
+    message: String,
+    // Byte range of the offending input.
+    span: Range<usize>,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}..{}", self.message, self.span.start, self.span.end)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Token {
+    True,
+    False,
+    Atom(char),
+    Not,
+    And,
+    Or,
+    Implies,
+    Iff,
+    LParen,
+    RParen,
+    Comma,
+    IffAll,
+    Let,
+    Define,
+    In,
+}
+
+impl ParseError
+{
+    fn new(message : &str, span : Range<usize>) -> ParseError
+    {
+        ParseError { message: message.to_string(), span: span }
+    }
+}
+
+// Split the input into tokens.  Bad input is reported and skipped, so that tokenizing always carries on
+// to the end and every problem is found.
+fn tokenize(input : &str) -> (Vec<(Token, Range<usize>)>, Vec<ParseError>)
+{
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some((start, c)) = chars.next()
+    {
+        let next = chars.peek().map(|&(_, n)| n);
+        let token = match c
+        {
+            _ if c.is_whitespace() => continue,
+            '/' if next == Some('/') => {
+                while let Some(&(_, n)) = chars.peek() {
+                    if n == '\n' { break; }
+                    chars.next();
+                }
+                continue;
+            }
+            '/' if next == Some('*') => {
+                chars.next();
+                let mut closed = false;
+                while let Some((_, n)) = chars.next() {
+                    if n == '*' && chars.peek().map(|&(_, m)| m) == Some('/') {
+                        chars.next();
+                        closed = true;
+                        break;
+                    }
+                }
+                if !closed {
+                    errors.push(ParseError::new("unterminated block comment", start..input.len()));
+                }
+                continue;
+            }
+            '~' | '!' | '¬' => Token::Not,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
+            '∧' => Token::And,
+            '∨' => Token::Or,
+            '→' => Token::Implies,
+            '↔' => Token::Iff,
+            '⊤' => Token::True,
+            '⊥' => Token::False,
+            '&' if next == Some('&') => { chars.next(); Token::And }
+            '|' if next == Some('|') => { chars.next(); Token::Or }
+            '-' if next == Some('>') => { chars.next(); Token::Implies }
+            ':' if next == Some('=') => { chars.next(); Token::Define }
+            '<' if next == Some('-') => {
+                chars.next();
+                if chars.peek().map(|&(_, n)| n) == Some('>') {
+                    chars.next();
+                } else {
+                    errors.push(ParseError::new("expected '<->'", start..start + 2));
+                }
+                Token::Iff
+            }
+            _ if c.is_alphanumeric() => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, n)) = chars.peek() {
+                    if !n.is_alphanumeric() { break; }
+                    end = i + n.len_utf8();
+                    chars.next();
+                }
+                match &input[start..end] {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "TRUE" | "true" => Token::True,
+                    "FALSE" | "false" => Token::False,
+                    "IFF" => Token::IffAll,
+                    "let" => Token::Let,
+                    "in" => Token::In,
+                    _ if end == start + c.len_utf8() => Token::Atom(c),
+                    _ => {
+                        errors.push(ParseError::new("atoms are single characters", start..end));
+                        Token::Atom(c)
+                    }
+                }
+            }
+            _ => {
+                errors.push(ParseError::new("unexpected character", start..start + c.len_utf8()));
+                continue;
+            }
+        };
+        let end = chars.peek().map(|&(i, _)| i).unwrap_or(input.len());
+        tokens.push((token, start..end));
+    }
+    (tokens, errors)
+}
+
+// A recursive descent parser that records errors and keeps going: a missing token is reported and
+// treated as if it were there, and a missing operand is reported and replaced by FALSE.  The formula is
+// only meaningful if no errors were recorded.
+struct Parser<'a>
+{
+    tokens: &'a [(Token, Range<usize>)],
+    pos: usize,
+    // Length of the input, used as the span of errors at the end.
+    end: usize,
+    errors: Vec<ParseError>,
+}
+
+impl<'a> Parser<'a>
+{
+    fn peek(&self) -> Option<Token>
+    {
+        self.tokens.get(self.pos).map(|&(t, _)| t)
+    }
+
+    fn span(&self) -> Range<usize>
+    {
+        match self.tokens.get(self.pos) {
+            Some(&(_, ref span)) => span.clone(),
+            None => self.end..self.end,
+        }
+    }
+
+    fn error(&mut self, message : &str)
+    {
+        let span = self.span();
+        // One report per site is enough.
+        if self.errors.last().map_or(true, |e| e.span != span) {
+            self.errors.push(ParseError::new(message, span));
+        }
+    }
+
+    fn expect(&mut self, token : Token, message : &str)
+    {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+        } else {
+            self.error(message);
+        }
+    }
+
+    fn iff(&mut self) -> (Formula, SpanTree)
+    {
+        let l = self.implies();
+        if self.peek() == Some(Token::Iff) {
+            self.pos += 1;
+            let r = self.iff();
+            let span = l.1.span.start..r.1.span.end;
+            return (Formula::Iff { l: box l.0, r: box r.0 }, SpanTree { span: span, children: vec!(l.1, r.1) });
+        }
+        l
+    }
+
+    fn implies(&mut self) -> (Formula, SpanTree)
+    {
+        let l = self.or();
+        if self.peek() == Some(Token::Implies) {
+            self.pos += 1;
+            let r = self.implies();
+            let span = l.1.span.start..r.1.span.end;
+            return (Formula::Implies { l: box l.0, r: box r.0 }, SpanTree { span: span, children: vec!(l.1, r.1) });
+        }
+        l
+    }
+
+    fn or(&mut self) -> (Formula, SpanTree)
+    {
+        let mut v = vec!(self.and());
+        while self.peek() == Some(Token::Or) {
+            self.pos += 1;
+            v.push(self.and());
+        }
+        if v.len() == 1 { v.pop().unwrap() } else { nary(v, Formula::Or) }
+    }
+
+    fn and(&mut self) -> (Formula, SpanTree)
+    {
+        let mut v = vec!(self.unary());
+        while self.peek() == Some(Token::And) {
+            self.pos += 1;
+            v.push(self.unary());
+        }
+        if v.len() == 1 { v.pop().unwrap() } else { nary(v, Formula::And) }
+    }
+
+    // A leaf for the current token, which is consumed.
+    fn leaf(&mut self, f : Formula) -> (Formula, SpanTree)
+    {
+        let span = self.span();
+        self.pos += 1;
+        (f, SpanTree { span: span, children: vec!() })
+    }
+
+    fn unary(&mut self) -> (Formula, SpanTree)
+    {
+        let start = self.span().start;
+        match self.peek()
+        {
+            Some(Token::Not) => {
+                self.pos += 1;
+                let (n, spans) = self.unary();
+                let span = start..spans.span.end;
+                (Formula::Not(box n), SpanTree { span: span, children: vec!(spans) })
+            }
+            Some(Token::True) => self.leaf(Formula::True),
+            Some(Token::False) => self.leaf(Formula::False),
+            Some(Token::Atom(c)) => self.leaf(Formula::Atom(c)),
+            Some(Token::IffAll) => {
+                self.pos += 1;
+                self.expect(Token::LParen, "expected '(' after 'IFF'");
+                let mut v = Vec::new();
+                if self.peek() != Some(Token::RParen) {
+                    v.push(self.iff());
+                    while self.peek() == Some(Token::Comma) {
+                        self.pos += 1;
+                        v.push(self.iff());
+                    }
+                }
+                let end = self.span().end;
+                self.expect(Token::RParen, "expected ',' or ')'");
+                let (fs, spans) = v.into_iter().unzip();
+                (Formula::IffAll(fs), SpanTree { span: start..end, children: spans })
+            }
+            Some(Token::Let) => {
+                self.pos += 1;
+                let name = match self.peek() {
+                    Some(Token::Atom(c)) => { self.pos += 1; c }
+                    _ => { self.error("expected a name after 'let'"); '_' }
+                };
+                self.expect(Token::Define, "expected ':='");
+                let value = self.iff();
+                self.expect(Token::In, "expected 'in'");
+                let body = self.iff();
+                // Substituted copies of the value keep the value's spans.
+                let spans = substitute_spans(&body.0, &body.1, name, &value.1);
+                (substitute(&body.0, name, &value.0), spans)
+            }
+            Some(Token::LParen) => {
+                self.pos += 1;
+                let (inner, mut spans) = self.iff();
+                let end = self.span().end;
+                self.expect(Token::RParen, "expected ')'");
+                spans.span = start..end;
+                (inner, spans)
+            }
+            // Leave the token for whoever can use it: binary connectives are picked up by the loops above,
+            // and anything else by the caller or, failing that, `parse_all_errors`.
+            Some(_) => {
+                self.error("expected an atom, '~' or '('");
+                (Formula::False, SpanTree { span: start..start, children: vec!() })
+            }
+            None => {
+                self.error("unexpected end of input");
+                (Formula::False, SpanTree { span: start..start, children: vec!() })
+            }
+        }
+    }
+}
+
+// Combine the operands of an AND or OR.
+fn nary(v : Vec<(Formula, SpanTree)>, connective : fn(Vec<Formula>) -> Formula) -> (Formula, SpanTree)
+{
+    let span = v[0].1.span.start..v[v.len() - 1].1.span.end;
+    let (fs, spans) = v.into_iter().unzip();
+    (connective(fs), SpanTree { span: span, children: spans })
+}
+
+// The spans of `substitute(f, var, _)`, given the spans of `f` and of the substituted formula.
+fn substitute_spans(f : &Formula, spans : &SpanTree, var : char, value : &SpanTree) -> SpanTree
+{
+    match f
+    {
+        &Formula::Atom(c) if c == var => value.clone(),
+        _ => SpanTree {
+            span: spans.span.clone(),
+            children: children(f).into_iter().zip(&spans.children).map(|(c, s)| substitute_spans(c, s, var, value)).collect(),
+        },
+    }
+}
+
+// Where each node of a parsed formula came from: a tree of the same shape as the formula, holding the
+// byte range of each node in the input, with children in the order of `children()`.  A parenthesized
+// subformula's range includes the parentheses.  Nothing else keeps spans, so they only describe the
+// formula exactly as parsed.
+#[derive(Clone, PartialEq, Debug)]
+struct SpanTree
+{
+    span: Range<usize>,
+    children: Vec<SpanTree>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Spanned
+{
+    formula: Formula,
+    spans: SpanTree,
+}
+
+fn parse(input : &str) -> Result<Formula, ParseError>
+{
+    parse_all_errors(input).map_err(|mut errors| errors.remove(0))
+}
+
+// Like `parse`, but on failure reports every error found rather than just the first, in input order.
+fn parse_all_errors(input : &str) -> Result<Formula, Vec<ParseError>>
+{
+    parse_spanned_all_errors(input).map(|s| s.formula)
+}
+
+// Like `parse`, but also returning the source span of every node.
+fn parse_spanned(input : &str) -> Result<Spanned, ParseError>
+{
+    parse_spanned_all_errors(input).map_err(|mut errors| errors.remove(0))
+}
+
+fn parse_spanned_all_errors(input : &str) -> Result<Spanned, Vec<ParseError>>
+{
+    let (tokens, mut errors) = tokenize(input);
+    let mut parser = Parser { tokens: &tokens, pos: 0, end: input.len(), errors: Vec::new() };
+    let (f, spans) = parser.iff();
+
+    // Skip over anything left after the formula, reporting it, and carry on looking for errors in what
+    // follows, which is most likely the rest of the same formula (as after a stray ')').
+    while parser.pos < tokens.len()
+    {
+        if parser.peek() == Some(Token::RParen) {
+            parser.error("unmatched ')'");
+        } else {
+            parser.error("unexpected input after formula");
+        }
+        parser.pos += 1;
+        match parser.peek()
+        {
+            None => break,
+            Some(Token::And) | Some(Token::Or) | Some(Token::Implies) | Some(Token::Iff) => { parser.pos += 1; parser.iff(); }
+            Some(_) => { parser.iff(); }
+        }
+    }
+
+    errors.extend(parser.errors);
+    errors.sort_by_key(|e| (e.span.start, e.span.end));
+    if errors.is_empty() { Ok(Spanned { formula: f, spans: spans }) } else { Err(errors) }
+}
+
+// SAT solving.  `Solver` is an incremental CDCL solver over clause lists: clauses can be added between
+// calls to `solve`, and everything learned so far is kept for the next call.  It uses two watched
+// literals per clause for propagation and learns first-UIP clauses on conflict.
+//
+// Internally variables are numbered from 0 in order of first appearance, and a literal is encoded as
+// `2 * var + negated`, so that its complement is `lit ^ 1`.
+
+#[derive(Clone, PartialEq, Debug)]
+enum SatResult {
+    Sat(HashMap<char, bool>),
+    Unsat,
+}
+
+#[derive(Clone, Debug)]
+struct SolverClause
+{
+    lits: Vec<usize>,
+    learned: bool,
+    // Literal block distance: the number of distinct decision levels among the literals when the clause
+    // was learned.  Low values mark the clauses worth keeping.
+    lbd: usize,
+    // Bumped whenever a learned clause takes part in conflict analysis, like the variable activities.
+    activity: f64,
+}
+
+// When the solver abandons its current assignment (keeping everything it has learned) to start the
+// search again from level 0.  The limits are numbers of conflicts since the previous restart.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum RestartStrategy {
+    Never,
+    // `unit` times the Luby sequence 1, 1, 2, 1, 1, 2, 4, 1, ...
+    Luby { unit: u64 },
+    // `first`, then each limit `factor` times the last.
+    Geometric { first: u64, factor: f64 },
+}
+
+impl RestartStrategy
+{
+    // The conflict limit before restart number `n` (from 0), or None for no restarts.  Never less than 1,
+    // even for a zero unit or a factor below 1, as a limit of 0 would restart before every conflict and
+    // the search would never get anywhere.
+    fn interval(&self, n : u64) -> Option<u64>
+    {
+        let limit = match *self
+        {
+            RestartStrategy::Never => return None,
+            RestartStrategy::Luby { unit } => unit.saturating_mul(luby(n)),
+            RestartStrategy::Geometric { first, factor } => (first as f64 * factor.powi(n as i32)).round() as u64,
+        };
+        Some(limit.max(1))
+    }
+}
+
+// Element `i` (from 0) of the Luby sequence.  The sequence is made of blocks: block k repeats every block
+// before it twice and then ends in 2^k.
+fn luby(i : u64) -> u64
+{
+    let mut i = i;
+    let (mut size, mut seq) = (1u64, 0u32);
+    while size < i + 1 {
+        seq += 1;
+        size = 2 * size + 1;
+    }
+    while size - 1 != i {
+        size = (size - 1) >> 1;
+        seq -= 1;
+        i = i % size;
+    }
+    1 << seq
+}
+
+// How the solver picks the variable for its next decision.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Branching {
+    // The first unassigned variable, in the order variables were first seen.
+    Static,
+    // The unassigned variable with the highest activity.  Every variable involved in a conflict has its
+    // activity bumped, and after each conflict all activities are scaled by `decay` (below 1), so
+    // recent conflicts count the most.
+    Vsids { decay: f64 },
+}
+
+// Where conflict analysis stops resolving away literals of the conflict level, which decides the one
+// literal of that level the learned clause keeps.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum UipScheme {
+    // The first unique implication point: the literal of the conflict level nearest the conflict that
+    // every path from that level's decision to the conflict passes through.  This gives the shortest
+    // clauses and is the usual choice.
+    FirstUip,
+    // The decision of the conflict level itself, so the learned clause keeps only decisions and literals
+    // of earlier levels.
+    DecisionUip,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct SolverConfig
+{
+    restart_strategy: RestartStrategy,
+    branching: Branching,
+    // Decide each variable the way it was last assigned, instead of always false.
+    phase_saving: bool,
+    // Once there are more learned clauses than this, the worst of them (highest LBD, then least active)
+    // are deleted until half as many remain.  None keeps every learned clause.
+    max_learned: Option<usize>,
+    // Learned clauses with an LBD at most this are never deleted.
+    permanent_lbd: usize,
+    uip_scheme: UipScheme,
+}
+
+impl Default for SolverConfig
+{
+    fn default() -> SolverConfig
+    {
+        SolverConfig {
+            restart_strategy: RestartStrategy::Luby { unit: 100 },
+            branching: Branching::Vsids { decay: 0.95 },
+            phase_saving: true,
+            max_learned: Some(10_000),
+            permanent_lbd: 2,
+            uip_scheme: UipScheme::FirstUip,
+        }
+    }
+}
+
+struct Solver
+{
+    config: SolverConfig,
+    names: Vec<char>,
+    ids: HashMap<char, usize>,
+    clauses: Vec<SolverClause>,
+    // Indexed by literal: the clauses watching that literal.
+    watches: Vec<Vec<usize>>,
+    values: Vec<Option<bool>>,
+    levels: Vec<usize>,
+    reasons: Vec<Option<usize>>,
+    // VSIDS scores, and the amount the next bump adds.  Growing the bump instead of shrinking every score
+    // decays them all at once.
+    activity: Vec<f64>,
+    bump: f64,
+    // The same for learned clauses.
+    clause_bump: f64,
+    // The number of learned clauses in `clauses`.
+    learned: usize,
+    // The value each variable had when it was last unassigned (initially false).
+    phases: Vec<bool>,
+    trail: Vec<usize>,
+    // Index into `trail` where each decision level starts.
+    trail_lim: Vec<usize>,
+    // Next trail entry to propagate.
+    qhead: usize,
+    // Set once the clauses are known to be unsatisfiable at level 0; nothing can undo that.
+    unsat: bool,
+    decisions: u64,
+    conflicts: u64,
+    restarts: u64,
+    // The last model found, while it still satisfies every clause added since.
+    model: Option<HashMap<char, bool>>,
+    // After an `Unsat` answer from `solve_with`, the assumptions it needed: the clauses with any one of
+    // them are unsatisfiable.  Empty when the clauses are unsatisfiable on their own.
+    failed: Vec<Literal>,
+}
+
+fn lit_var(lit : usize) -> usize
+{
+    lit >> 1
+}
+
+impl Solver
+{
+    fn new() -> Solver
+    {
+        Solver::with_config(SolverConfig::default())
+    }
+
+    fn with_config(config : SolverConfig) -> Solver
+    {
+        Solver {
+            config: config,
+            names: Vec::new(),
+            ids: HashMap::new(),
+            clauses: Vec::new(),
+            watches: Vec::new(),
+            values: Vec::new(),
+            levels: Vec::new(),
+            reasons: Vec::new(),
+            activity: Vec::new(),
+            bump: 1.0,
+            clause_bump: 1.0,
+            learned: 0,
+            phases: Vec::new(),
+            trail: Vec::new(),
+            trail_lim: Vec::new(),
+            qhead: 0,
+            unsat: false,
+            decisions: 0,
+            conflicts: 0,
+            restarts: 0,
+            model: None,
+            failed: Vec::new(),
+        }
+    }
+
+    fn from_clauses(clauses : &[Vec<Literal>]) -> Solver
+    {
+        let mut solver = Solver::new();
+        for c in clauses {
+            solver.add_clause(c);
+        }
+        solver
+    }
+
+    fn var(&mut self, atom : char) -> usize
+    {
+        if let Some(&v) = self.ids.get(&atom) {
+            return v;
+        }
+        let v = self.names.len();
+        self.names.push(atom);
+        self.ids.insert(atom, v);
+        self.watches.push(Vec::new());
+        self.watches.push(Vec::new());
+        self.values.push(None);
+        self.levels.push(0);
+        self.reasons.push(None);
+        self.activity.push(0.0);
+        self.phases.push(false);
+        v
+    }
+
+    fn lit(&mut self, l : Literal) -> usize
+    {
+        2 * self.var(l.atom) + if l.negated { 1 } else { 0 }
+    }
+
+    fn literal(&self, lit : usize) -> Literal
+    {
+        Literal { atom: self.names[lit_var(lit)], negated: lit & 1 == 1 }
+    }
+
+    fn lit_value(&self, lit : usize) -> Option<bool>
+    {
+        self.values[lit_var(lit)].map(|v| v != (lit & 1 == 1))
+    }
+
+    fn decision_level(&self) -> usize
+    {
+        self.trail_lim.len()
+    }
+
+    fn num_clauses(&self) -> usize
+    {
+        self.clauses.len()
+    }
+
+    fn num_learned(&self) -> usize
+    {
+        self.learned
+    }
+
+    // Add a clause between calls to `solve`.  Clauses that are already false at level 0 make the solver
+    // permanently unsatisfiable.
+    fn add_clause(&mut self, clause : &[Literal])
+    {
+        self.backtrack(0);
+        let keeps_model = match self.model {
+            Some(ref mut m) => {
+                let satisfied = clause.iter().any(|l| m.get(&l.atom).map_or(false, |&v| v != l.negated));
+                // Atoms new to the model can take any value.
+                for l in clause { m.entry(l.atom).or_insert(false); }
+                satisfied
+            }
+            None => false,
+        };
+        if !keeps_model {
+            self.model = None;
+        }
+
+        let mut lits : Vec<usize> = clause.iter().map(|&l| self.lit(l)).collect();
+        lits.sort();
+        lits.dedup();
+        if lits.windows(2).any(|w| w[0] ^ 1 == w[1]) {
+            // Contains P and ~P.
+            return;
+        }
+        self.attach(lits, false, 0);
+    }
+
+    // Store a clause, watch it, and deal with it being unit or false under the current assignment.  Only
+    // called at level 0 or, for learned clauses, right after backtracking to the assertion level.
+    fn attach(&mut self, mut lits : Vec<usize>, learned : bool, lbd : usize) -> usize
+    {
+        // Watch true or unassigned literals in preference to false ones, and of the false ones the most
+        // recently assigned.
+        {
+            let values = &self.values;
+            let levels = &self.levels;
+            let rank = |l : usize| match values[lit_var(l)].map(|v| v != (l & 1 == 1)) {
+                Some(false) => levels[lit_var(l)],
+                _ => usize::MAX,
+            };
+            lits.sort_by_key(|&l| Reverse(rank(l)));
+        }
+
+        let index = self.clauses.len();
+        if lits.len() >= 2 {
+            self.watches[lits[0]].push(index);
+            self.watches[lits[1]].push(index);
+        }
+        let first = lits.first().cloned();
+        let second = lits.get(1).cloned();
+        self.clauses.push(SolverClause { lits: lits, learned: learned, lbd: lbd, activity: 0.0 });
+        if learned {
+            self.learned += 1;
+        }
+
+        match first
+        {
+            None => self.unsat = true,
+            Some(l0) => {
+                let unit = second.map_or(true, |l1| self.lit_value(l1) == Some(false));
+                match self.lit_value(l0)
+                {
+                    Some(false) => self.unsat = true,
+                    None if unit => {
+                        self.enqueue(l0, Some(index));
+                        if self.decision_level() == 0 && self.propagate().is_some() {
+                            self.unsat = true;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        index
+    }
+
+    fn enqueue(&mut self, lit : usize, reason : Option<usize>)
+    {
+        let v = lit_var(lit);
+        self.values[v] = Some(lit & 1 == 0);
+        self.levels[v] = self.decision_level();
+        self.reasons[v] = reason;
+        self.trail.push(lit);
+    }
+
+    // Unit propagation over the watched literals.  Returns the index of a conflicting clause, if any.
+    fn propagate(&mut self) -> Option<usize>
+    {
+        while self.qhead < self.trail.len()
+        {
+            let false_lit = self.trail[self.qhead] ^ 1;
+            self.qhead += 1;
+
+            let watching = mem::take(&mut self.watches[false_lit]);
+            let mut kept = Vec::with_capacity(watching.len());
+            let mut conflict = None;
+            for (i, &ci) in watching.iter().enumerate()
+            {
+                if conflict.is_some() {
+                    kept.extend_from_slice(&watching[i..]);
+                    break;
+                }
+
+                // Keep the false literal in position 1.
+                if self.clauses[ci].lits[0] == false_lit {
+                    self.clauses[ci].lits.swap(0, 1);
+                }
+                let other = self.clauses[ci].lits[0];
+                if self.lit_value(other) == Some(true) {
+                    kept.push(ci);
+                    continue;
+                }
+
+                let replacement = (2..self.clauses[ci].lits.len()).find(|&k| self.lit_value(self.clauses[ci].lits[k]) != Some(false));
+                if let Some(k) = replacement {
+                    self.clauses[ci].lits.swap(1, k);
+                    let new_watch = self.clauses[ci].lits[1];
+                    self.watches[new_watch].push(ci);
+                    continue;
+                }
+
+                kept.push(ci);
+                if self.lit_value(other) == Some(false) {
+                    conflict = Some(ci);
+                } else {
+                    self.enqueue(other, Some(ci));
+                }
+            }
+            self.watches[false_lit] = kept;
+
+            if conflict.is_some() {
+                self.qhead = self.trail.len();
+                return conflict;
+            }
+        }
+        None
+    }
+
+    // Conflict analysis, resolving back to the UIP that `config.uip_scheme` asks for.  Returns the learned
+    // clause, with the asserting literal first and a literal of the backtrack level second, together with
+    // that level.
+    fn analyze(&mut self, conflict : usize) -> (Vec<usize>, usize)
+    {
+        let mut seen = vec!(false; self.names.len());
+        // Every variable resolved on or kept in the learned clause.
+        let mut bumped = Vec::new();
+        let mut learnt = vec!(0);
+        let mut pending = 0;
+        let mut index = self.trail.len();
+        let mut clause = conflict;
+        let mut p = None;
+        loop
+        {
+            if self.clauses[clause].learned {
+                self.bump_clause(clause);
+            }
+            for &q in &self.clauses[clause].lits
+            {
+                let v = lit_var(q);
+                if Some(v) == p.map(lit_var) || seen[v] || self.levels[v] == 0 {
+                    continue;
+                }
+                seen[v] = true;
+                bumped.push(v);
+                if self.levels[v] == self.decision_level() {
+                    pending += 1;
+                } else {
+                    learnt.push(q);
+                }
+            }
+
+            // The next literal of the current level to resolve on, walking back along the trail.
+            loop {
+                index -= 1;
+                if seen[lit_var(self.trail[index])] { break; }
+            }
+            let lit = self.trail[index];
+            seen[lit_var(lit)] = false;
+            p = Some(lit);
+            pending -= 1;
+            if pending == 0 && (self.config.uip_scheme == UipScheme::FirstUip || self.reasons[lit_var(lit)].is_none()) {
+                break;
+            }
+            clause = self.reasons[lit_var(lit)].expect("only decisions have no reason");
+        }
+        learnt[0] = p.unwrap() ^ 1;
+        for v in bumped {
+            self.bump_activity(v);
+        }
+
+        let mut level = 0;
+        for i in 1..learnt.len() {
+            let l = self.levels[lit_var(learnt[i])];
+            if l > level {
+                level = l;
+                learnt.swap(1, i);
+            }
+        }
+        (learnt, level)
+    }
+
+    // The assumptions that force the assumption `a` false: walking back along the trail from `a`, through
+    // the reasons of the literals it depends on, to the decisions, all of which are assumptions while
+    // `solve_with` is still making them.  `a` comes first.
+    fn analyze_final(&self, a : usize) -> Vec<Literal>
+    {
+        let mut failed = vec!(self.literal(a));
+        if self.levels[lit_var(a)] == 0 {
+            return failed;
+        }
+        let mut seen = vec!(false; self.names.len());
+        seen[lit_var(a)] = true;
+        for &lit in self.trail[self.trail_lim[0]..].iter().rev()
+        {
+            let v = lit_var(lit);
+            if !seen[v] {
+                continue;
+            }
+            match self.reasons[v]
+            {
+                None => failed.push(self.literal(lit)),
+                Some(clause) => for &q in &self.clauses[clause].lits {
+                    if self.levels[lit_var(q)] > 0 {
+                        seen[lit_var(q)] = true;
+                    }
+                },
+            }
+        }
+        failed
+    }
+
+    fn bump_activity(&mut self, v : usize)
+    {
+        self.activity[v] += self.bump;
+        if self.activity[v] > 1e100 {
+            for a in self.activity.iter_mut() {
+                *a *= 1e-100;
+            }
+            self.bump *= 1e-100;
+        }
+    }
+
+    fn decay_activity(&mut self)
+    {
+        if let Branching::Vsids { decay } = self.config.branching {
+            self.bump /= decay;
+        }
+        self.clause_bump /= 0.999;
+    }
+
+    fn bump_clause(&mut self, ci : usize)
+    {
+        self.clauses[ci].activity += self.clause_bump;
+        if self.clauses[ci].activity > 1e100 {
+            for c in self.clauses.iter_mut() {
+                c.activity *= 1e-100;
+            }
+            self.clause_bump *= 1e-100;
+        }
+    }
+
+    // Apply `config.max_learned`.  This can happen at any level: a clause that is the reason for a current
+    // assignment is never deleted, and the watches and reasons are renumbered for the clauses that stay.
+    fn reduce_learned(&mut self)
+    {
+        let max = match self.config.max_learned { Some(max) if self.learned > max => max, _ => return };
+        let locked : BTreeSet<usize> = self.trail.iter().filter_map(|&l| self.reasons[lit_var(l)]).collect();
+        let mut candidates : Vec<usize> = (0..self.clauses.len())
+            .filter(|&ci| self.clauses[ci].learned && self.clauses[ci].lbd > self.config.permanent_lbd && !locked.contains(&ci))
+            .collect();
+        {
+            let clauses = &self.clauses;
+            candidates.sort_by(|&a, &b| clauses[b].lbd.cmp(&clauses[a].lbd)
+                .then(clauses[a].activity.partial_cmp(&clauses[b].activity).unwrap()));
+        }
+        let doomed : BTreeSet<usize> = candidates.into_iter().take(self.learned - max / 2).collect();
+        if doomed.is_empty() {
+            return;
+        }
+
+        let mut renumbered = vec!(None; self.clauses.len());
+        let mut kept = Vec::with_capacity(self.clauses.len() - doomed.len());
+        for (ci, c) in mem::replace(&mut self.clauses, Vec::new()).into_iter().enumerate() {
+            if !doomed.contains(&ci) {
+                renumbered[ci] = Some(kept.len());
+                kept.push(c);
+            }
+        }
+        self.clauses = kept;
+        self.learned -= doomed.len();
+        for r in self.reasons.iter_mut() {
+            *r = r.and_then(|ci| renumbered[ci]);
+        }
+        // Every clause of two or more literals is watched by its first two.
+        for w in self.watches.iter_mut() {
+            w.clear();
+        }
+        for (ci, c) in self.clauses.iter().enumerate() {
+            if c.lits.len() >= 2 {
+                self.watches[c.lits[0]].push(ci);
+                self.watches[c.lits[1]].push(ci);
+            }
+        }
+    }
+
+    fn lbd(&self, lits : &[usize]) -> usize
+    {
+        let levels : BTreeSet<usize> = lits.iter().map(|&l| self.levels[lit_var(l)]).collect();
+        levels.len()
+    }
+
+    fn backtrack(&mut self, level : usize)
+    {
+        if self.decision_level() <= level {
+            return;
+        }
+        let start = self.trail_lim[level];
+        for &lit in &self.trail[start..] {
+            let v = lit_var(lit);
+            if self.config.phase_saving {
+                self.phases[v] = lit & 1 == 0;
+            }
+            self.values[v] = None;
+            self.reasons[v] = None;
+        }
+        self.trail.truncate(start);
+        self.trail_lim.truncate(level);
+        self.qhead = start;
+    }
+
+    // The next decision: a variable chosen according to `config.branching` (ties in activity go to the
+    // variable seen first), set to its saved phase.
+    fn pick_branch(&self) -> Option<usize>
+    {
+        let mut unassigned = (0..self.names.len()).filter(|&v| self.values[v].is_none());
+        let v = match self.config.branching
+        {
+            Branching::Static => unassigned.next(),
+            Branching::Vsids { .. } => unassigned.fold(None, |best : Option<usize>, v| match best {
+                Some(b) if self.activity[b] >= self.activity[v] => Some(b),
+                _ => Some(v),
+            }),
+        };
+        v.map(|v| 2 * v + if self.phases[v] { 0 } else { 1 })
+    }
+
+    fn solve(&mut self) -> SatResult
+    {
+        self.solve_with(&[])
+    }
+
+    // Solve under assumptions: literals that must hold for this call only.  They are made as the first
+    // decisions, one level each, so everything learned stays valid without them.  An `Unsat` answer
+    // caused by the assumptions leaves the solver usable for other assumptions.
+    fn solve_with(&mut self, assumptions : &[Literal]) -> SatResult
+    {
+        let assumptions : Vec<usize> = assumptions.iter().map(|&l| self.lit(l)).collect();
+        self.backtrack(0);
+        self.failed.clear();
+        if self.unsat {
+            return SatResult::Unsat;
+        }
+        let mut restart = 0;
+        let mut limit = self.config.restart_strategy.interval(restart);
+        let mut conflicts_since_restart = 0;
+        loop
+        {
+            if let Some(conflict) = self.propagate()
+            {
+                self.conflicts += 1;
+                conflicts_since_restart += 1;
+                if self.decision_level() == 0 {
+                    self.unsat = true;
+                    return SatResult::Unsat;
+                }
+                let (learnt, level) = self.analyze(conflict);
+                self.decay_activity();
+                let lbd = self.lbd(&learnt);
+                self.backtrack(level);
+                self.attach(learnt, true, lbd);
+                if self.unsat {
+                    return SatResult::Unsat;
+                }
+                self.reduce_learned();
+            }
+            else if limit.map_or(false, |l| conflicts_since_restart >= l)
+            {
+                self.backtrack(0);
+                self.restarts += 1;
+                restart += 1;
+                limit = self.config.restart_strategy.interval(restart);
+                conflicts_since_restart = 0;
+            }
+            else if self.decision_level() < assumptions.len()
+            {
+                let a = assumptions[self.decision_level()];
+                match self.lit_value(a)
+                {
+                    Some(false) => {
+                        self.failed = self.analyze_final(a);
+                        self.backtrack(0);
+                        return SatResult::Unsat;
+                    }
+                    // Already implied; keep one level per assumption anyway.
+                    Some(true) => self.trail_lim.push(self.trail.len()),
+                    None => {
+                        self.trail_lim.push(self.trail.len());
+                        self.enqueue(a, None);
+                    }
+                }
+            }
+            else
+            {
+                match self.pick_branch()
+                {
+                    Some(lit) => {
+                        self.decisions += 1;
+                        self.trail_lim.push(self.trail.len());
+                        self.enqueue(lit, None);
+                    }
+                    None => {
+                        let model : HashMap<char, bool> = self.names.iter().cloned().zip(self.values.iter().map(|v| v.unwrap())).collect();
+                        self.model = Some(model.clone());
+                        self.backtrack(0);
+                        return SatResult::Sat(model);
+                    }
+                }
+            }
+        }
+    }
+
+    // Add a clause and report whether everything asserted so far is still satisfiable.  When the last model
+    // found also satisfies the new clause it is returned straight away, so a stream of clauses that
+    // agree with the current solution costs no search at all.
+    fn assert_and_check(&mut self, clause : Vec<Literal>) -> SatResult
+    {
+        self.add_clause(&clause);
+        if self.unsat {
+            return SatResult::Unsat;
+        }
+        match self.model
+        {
+            Some(ref m) => SatResult::Sat(m.clone()),
+            None => self.solve(),
+        }
+    }
+
+    // Step through a search by hand: make `l` a decision at a new level and propagate it.  Returns false if
+    // `l` is already false or propagation reaches a conflict.  The assignment is left in place for
+    // inspection (e.g. by `implication_graph_dot`) until the next `backtrack(0)`, which adding a clause or
+    // solving also does first.
+    fn decide(&mut self, l : Literal) -> bool
+    {
+        let lit = self.lit(l);
+        if self.unsat || self.lit_value(lit) == Some(false) {
+            return false;
+        }
+        self.trail_lim.push(self.trail.len());
+        if self.lit_value(lit).is_none() {
+            self.enqueue(lit, None);
+        }
+        self.propagate().is_none()
+    }
+
+    // The current implication graph in Graphviz DOT: a node per assigned literal, labelled with its decision
+    // level (boxes for decisions), an edge to each propagated literal from the other literals of the clause
+    // that implied it, labelled with the clause's index, and if some clause is false, a conflict node with
+    // edges from that clause's literals.
+    fn implication_graph_dot(&self) -> String
+    {
+        let mut out = String::from("digraph implications {\n");
+        for &lit in &self.trail
+        {
+            let v = lit_var(lit);
+            let shape = if self.reasons[v].is_none() { ", shape=box" } else { "" };
+            out.push_str(&format!("    v{} [label=\"{} @{}\"{}];\n", v, self.literal(lit), self.levels[v], shape));
+        }
+        for &lit in &self.trail
+        {
+            let v = lit_var(lit);
+            if let Some(ci) = self.reasons[v] {
+                for &other in self.clauses[ci].lits.iter().filter(|&&o| lit_var(o) != v) {
+                    out.push_str(&format!("    v{} -> v{} [label=\"c{}\"];\n", lit_var(other), v, ci));
+                }
+            }
+        }
+        if let Some(ci) = (0..self.clauses.len()).find(|&ci| self.clauses[ci].lits.iter().all(|&l| self.lit_value(l) == Some(false)))
+        {
+            out.push_str(&format!("    conflict [label=\"conflict c{}\", shape=octagon];\n", ci));
+            for &l in &self.clauses[ci].lits {
+                out.push_str(&format!("    v{} -> conflict;\n", lit_var(l)));
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    // Shrink the clause database between calls to `solve`: drop clauses satisfied at level 0 and clauses
+    // subsumed by another clause, and drop learned clauses whose LBD exceeds `max_learned_lbd` if given.
+    // The solver gives the same answers afterwards, since every dropped clause is implied by what stays.
+    fn compact(&mut self, max_learned_lbd : Option<usize>)
+    {
+        self.backtrack(0);
+        let mut kept : Vec<SolverClause> = Vec::new();
+        for c in mem::take(&mut self.clauses)
+        {
+            let satisfied = c.lits.iter().any(|&l| self.lit_value(l) == Some(true));
+            let too_loose = c.learned && max_learned_lbd.map_or(false, |max| c.lbd > max);
+            if !satisfied && !too_loose {
+                kept.push(c);
+            }
+        }
+
+        // Backward subsumption, shortest clauses first so each one only needs checking against those
+        // before it.  Among equal clauses the first (preferring original over learned) is kept.  A learned
+        // clause that subsumes an original one takes its place as an original clause, so that it is never
+        // dropped later for its LBD, which would lose the original clause with it.
+        kept.sort_by_key(|c| (c.lits.len(), c.learned));
+        let sorted : Vec<BTreeSet<usize>> = kept.iter().map(|c| c.lits.iter().cloned().collect()).collect();
+        let mut subsumed = vec!(false; kept.len());
+        for i in 0..kept.len() {
+            if subsumed[i] { continue; }
+            for j in (i + 1)..kept.len() {
+                if !subsumed[j] && sorted[i].is_subset(&sorted[j]) {
+                    subsumed[j] = true;
+                    if !kept[j].learned {
+                        kept[i].learned = false;
+                    }
+                }
+            }
+        }
+
+        for w in self.watches.iter_mut() {
+            w.clear();
+        }
+        for r in self.reasons.iter_mut() {
+            *r = None;
+        }
+        self.learned = 0;
+        for (c, s) in kept.into_iter().zip(subsumed) {
+            if !s {
+                let ci = self.attach(c.lits, c.learned, c.lbd);
+                self.clauses[ci].activity = c.activity;
+            }
+        }
+    }
+}
+
+// True if both clause sets are satisfiable or both are unsatisfiable, whatever atoms they use.  This is
+// the relation between a formula and an encoding of it with auxiliary atoms, which is usually not
+// equivalent to it.
+fn equisatisfiable(a : &[Vec<Literal>], b : &[Vec<Literal>]) -> bool
+{
+    let sat = |clauses : &[Vec<Literal>]| Solver::from_clauses(clauses).solve() != SatResult::Unsat;
+    sat(a) == sat(b)
+}
+
+// The Unicode private use areas: the one in the Basic Multilingual Plane, then planes 15 and 16, between
+// them over 137,000 atoms.
+const FRESH_RANGES : [Range<u32>; 3] = [0xE000..0xF900, 0xF0000..0xFFFFE, 0x100000..0x10FFFE];
+
+// An atom not in `used`, which it is then added to.  Fresh atoms are taken from the Unicode private use
+// areas so they cannot clash with anything a user would write.  Each area is searched from just past
+// the last atom of it in `used`, which is most often the fresh atom taken before, and only then from its
+// start, so that taking many in a row is not quadratic.
+fn fresh_atom(used : &mut BTreeSet<char>) -> char
+{
+    let c = FRESH_RANGES.iter().filter_map(|r| {
+        let area = std::char::from_u32(r.start).unwrap()..=std::char::from_u32(r.end - 1).unwrap();
+        let after = used.range(area).next_back().map_or(r.start, |&c| c as u32 + 1);
+        (after..r.end).chain(r.clone()).filter_map(std::char::from_u32).find(|c| !used.contains(c))
+    }).next().expect("out of fresh atoms");
+    used.insert(c);
+    c
+}
+
+fn is_fresh(c : char) -> bool
+{
+    FRESH_RANGES.iter().any(|r| r.contains(&(c as u32)))
+}
+
+// Partial MaxSAT: an assignment satisfying every hard clause that maximizes the total weight of the
+// satisfied soft clauses, along with that weight, or None if the hard clauses are unsatisfiable.
+fn max_sat(hard : &[Vec<Literal>], soft : &[(Vec<Literal>, u64)]) -> Option<(HashMap<char, bool>, u64)>
+{
+    let mut solver = MaxSatSolver::new();
+    for clause in hard {
+        solver.add_hard(clause);
+    }
+    for &(ref clause, weight) in soft {
+        solver.add_soft(clause, weight);
+    }
+    solver.optimize(Direction::Maximize)
+}
+
+// Whether `MaxSatSolver::optimize` looks for the largest or the smallest total weight of satisfied soft
+// clauses.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Direction {
+    Maximize,
+    Minimize,
+}
+
+// Partial MaxSAT with clauses added between calls to `optimize`, for a problem that changes over time.
+// One incremental solver holds the hard clauses and everything the search adds to them, so the clauses
+// it learns are kept from one call to the next, and so are the cores each direction has relaxed (see
+// `optimize`).
+//
+// The solver's atoms are numbered as they are met, with `vocabulary_atom`, the selector and relaxation
+// atoms along with the clauses' own.  An atom the search adds is so never an atom of a clause, even of one
+// added after it, whatever atoms the clauses use.
+struct MaxSatSolver
+{
+    solver: Solver,
+    // The solver's atom for each atom of the clauses, and the other way round.
+    atoms: HashMap<char, char>,
+    names: HashMap<char, char>,
+    // The number of solver atoms handed out.
+    count: u32,
+    // Each soft clause, in the solver's atoms, and its weight.
+    soft: Vec<(Vec<Literal>, u64)>,
+    maximize: CoreSearch,
+    minimize: CoreSearch,
+}
+
+// Where `MaxSatSolver::optimize` has got to in one direction.  Each goal is a set of clauses to satisfy
+// for some weight, turned on by assuming its selector, which the solver holds as ~s v C for each of
+// the clauses C.
+#[derive(Default)]
+struct CoreSearch
+{
+    // Selector, clauses, weight.
+    goals: Vec<(Literal, Vec<Vec<Literal>>, u64)>,
+    // The number of soft clauses that have their goal in `goals`.
+    synced: usize,
+}
+
+impl MaxSatSolver
+{
+    fn new() -> MaxSatSolver
+    {
+        MaxSatSolver {
+            solver: Solver::new(),
+            atoms: HashMap::new(),
+            names: HashMap::new(),
+            count: 0,
+            soft: Vec::new(),
+            maximize: CoreSearch::default(),
+            minimize: CoreSearch::default(),
+        }
+    }
+
+    fn next_atom(&mut self) -> Literal
+    {
+        self.count += 1;
+        Literal { atom: vocabulary_atom(AtomId(self.count - 1)), negated: false }
+    }
+
+    // `clause` in the solver's atoms, numbering any it has not met yet.
+    fn translate(&mut self, clause : &[Literal]) -> Vec<Literal>
+    {
+        let mut translated = Vec::new();
+        for l in clause
+        {
+            let atom = match self.atoms.get(&l.atom).cloned() {
+                Some(atom) => atom,
+                None => {
+                    let atom = self.next_atom().atom;
+                    self.atoms.insert(l.atom, atom);
+                    self.names.insert(atom, l.atom);
+                    atom
+                }
+            };
+            translated.push(Literal { atom: atom, negated: l.negated });
+        }
+        translated
+    }
+
+    fn add_hard(&mut self, clause : &[Literal])
+    {
+        let clause = self.translate(clause);
+        self.solver.add_clause(&clause);
+    }
+
+    fn add_soft(&mut self, clause : &[Literal], weight : u64)
+    {
+        let clause = self.translate(clause);
+        self.soft.push((clause, weight));
+    }
+
+    // A fresh selector for `clauses`, added to the solver guarded by it.
+    fn select(&mut self, clauses : &[Vec<Literal>]) -> Literal
+    {
+        let s = self.next_atom();
+        for clause in clauses {
+            let mut guarded = vec!(s.negate());
+            guarded.extend(clause.iter().cloned());
+            self.solver.add_clause(&guarded);
+        }
+        s
+    }
+
+    // An assignment satisfying every hard clause whose total weight of satisfied soft clauses is the
+    // largest or smallest possible, with that weight, or None if the hard clauses are unsatisfiable.
+    //
+    // The search is core-guided: Fu-Malik, with WPM1's splitting of weights.  Each soft clause becomes a
+    // goal, the clause itself when maximizing and the negation of each of its literals when minimizing,
+    // and the solver is asked for a model meeting every goal, by assuming their selectors.  Failing that
+    // it names the selectors it needed, a core of goals that cannot all be met, of which at least the
+    // lightest weight w must be lost.  Each goal of the core keeps what it weighs beyond w, and a copy
+    // weighing w is relaxed by a fresh atom b added to each of its clauses, with at most one of the core's
+    // b atoms true.  The first model meeting every goal is then optimal.
+    //
+    // A core stays a core when clauses are added, so the goals of each direction are kept for the next
+    // call, which starts from them with any soft clauses added since.
+    fn optimize(&mut self, direction : Direction) -> Option<(HashMap<char, bool>, u64)>
+    {
+        if self.solver.solve() == SatResult::Unsat {
+            return None;
+        }
+        let mut search = mem::take(match direction {
+            Direction::Maximize => &mut self.maximize,
+            Direction::Minimize => &mut self.minimize,
+        });
+        while search.synced < self.soft.len()
+        {
+            let (clause, weight) = self.soft[search.synced].clone();
+            let clauses = match direction {
+                Direction::Maximize => vec!(clause),
+                Direction::Minimize => clause.iter().map(|l| vec!(l.negate())).collect(),
+            };
+            let s = self.select(&clauses);
+            search.goals.push((s, clauses, weight));
+            search.synced += 1;
+        }
+
+        let model = loop
+        {
+            let selectors : Vec<Literal> = search.goals.iter().map(|g| g.0).collect();
+            if let SatResult::Sat(m) = self.solver.solve_with(&selectors) {
+                break m;
+            }
+            let failed : BTreeSet<Literal> = self.solver.failed.iter().cloned().collect();
+            let core : Vec<usize> = (0..search.goals.len()).filter(|&i| failed.contains(&search.goals[i].0)).collect();
+            let w = core.iter().map(|&i| search.goals[i].2).min().expect("the hard clauses are satisfiable");
+            let mut relax = Vec::new();
+            for &i in &core
+            {
+                let b = self.next_atom();
+                let clauses : Vec<Vec<Literal>> = search.goals[i].1.iter().map(|c| {
+                    let mut c = c.clone();
+                    c.push(b);
+                    c
+                }).collect();
+                let s = self.select(&clauses);
+                search.goals[i].2 -= w;
+                search.goals.push((s, clauses, w));
+                relax.push(b);
+            }
+            for (i, &a) in relax.iter().enumerate() {
+                for &b in &relax[i + 1..] {
+                    self.solver.add_clause(&[a.negate(), b.negate()]);
+                }
+            }
+            search.goals.retain(|g| g.2 > 0);
+        };
+        match direction {
+            Direction::Maximize => self.maximize = search,
+            Direction::Minimize => self.minimize = search,
+        }
+
+        let satisfied = self.soft.iter().filter(|s| s.0.iter().any(|l| model[&l.atom] != l.negated)).map(|s| s.1).sum();
+        let names = &self.names;
+        let model = model.into_iter().filter_map(|(c, v)| names.get(&c).map(|&name| (name, v))).collect();
+        Some((model, satisfied))
+    }
+}
+
+// A solver holding each clause C as C v r for a fresh atom r, and the literals ~r that enforce the clauses
+// when assumed, in order.
+fn relaxed_solver(clauses : &[Vec<Literal>]) -> (Solver, Vec<Literal>)
+{
+    let mut used : BTreeSet<char> = clauses.iter().flat_map(|c| c.iter().map(|l| l.atom)).collect();
+    let mut solver = Solver::new();
+    let mut enforce = Vec::new();
+    for clause in clauses
+    {
+        let relax = Literal { atom: fresh_atom(&mut used), negated: false };
+        let mut relaxed = clause.clone();
+        relaxed.push(relax);
+        solver.add_clause(&relaxed);
+        enforce.push(relax.negate());
+    }
+    (solver, enforce)
+}
+
+// A minimal unsatisfiable subset of `clauses`, as indices in increasing order: the clauses are
+// unsatisfiable together, but dropping any one of them makes the rest satisfiable.  None if `clauses` is
+// satisfiable.
+//
+// Each clause is relaxed by `relaxed_solver` and enforced by assumption, and `minimal_core` does the rest.
+fn unsat_core(clauses : &[Vec<Literal>]) -> Option<Vec<usize>>
+{
+    let (mut solver, enforce) = relaxed_solver(clauses);
+    minimal_core(&mut solver, &enforce)
+}
+
+// A smallest-by-inclusion set of the assumptions `enforce` under which `solver` is unsatisfiable, as
+// indices into `enforce` in increasing order, or None if it is satisfiable under all of them.  Starting
+// from all of them, each in turn is left out, and stays out if the rest are still unsatisfiable.
+fn minimal_core(solver : &mut Solver, enforce : &[Literal]) -> Option<Vec<usize>>
+{
+    if solver.solve_with(enforce) != SatResult::Unsat {
+        return None;
+    }
+
+    let mut core : Vec<usize> = (0..enforce.len()).collect();
+    let mut i = 0;
+    while i < core.len()
+    {
+        let without : Vec<Literal> = core.iter().enumerate().filter(|&(j, _)| j != i).map(|(_, &k)| enforce[k]).collect();
+        if solver.solve_with(&without) == SatResult::Unsat {
+            core.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+    Some(core)
+}
+
+// Why `f` is unsatisfiable, as the conjunction of a minimal set of its top-level conjuncts (nested ANDs
+// count as one level) that are unsatisfiable together, or None if `f` is satisfiable.  The conjuncts are
+// kept as written, so the result reads like part of `f`; an unsatisfiable `f` that is not an AND is its
+// own explanation.
+//
+// Each conjunct is encoded by `circuit_clauses`, with the unit clause for its root relaxed so that it can
+// be enforced by assumption, and `minimal_core` picks the conjuncts.
+fn why_unsat(f : &Formula) -> Option<Formula>
+{
+    let conjuncts = match flatten(f.clone())
+    {
+        Formula::And(v) => v,
+        g @ _ => vec!(g),
+    };
+    let mut used : BTreeSet<char> = atoms(f).into_iter().collect();
+    let mut solver = Solver::new();
+    let mut enforce = Vec::new();
+    for g in &conjuncts
+    {
+        let (clauses, root) = circuit_clauses(g, &mut used);
+        for c in &clauses {
+            solver.add_clause(c);
+        }
+        let relax = Literal { atom: fresh_atom(&mut used), negated: false };
+        solver.add_clause(&[root, relax]);
+        enforce.push(relax.negate());
+    }
+    let core = minimal_core(&mut solver, &enforce)?;
+    Some(and_of(core.into_iter().map(|i| conjuncts[i].clone()).collect()))
+}
+
+// `unsat_core` for clauses that carry names, e.g. the requirements they were written from: the names of
+// the clauses in a minimal unsatisfiable subset, in their original order, or nothing if the clauses are
+// satisfiable.
+fn labeled_unsat_core(clauses : &[(String, Vec<Literal>)]) -> Vec<String>
+{
+    let bare : Vec<Vec<Literal>> = clauses.iter().map(|c| c.1.clone()).collect();
+    match unsat_core(&bare)
+    {
+        Some(core) => core.into_iter().map(|i| clauses[i].0.clone()).collect(),
+        None => vec!(),
+    }
+}
+
+// Every maximal satisfiable subset of `clauses`, as indices in increasing order: each subset is
+// satisfiable, and adding any other clause to it makes it unsatisfiable.  A satisfiable clause set has just
+// the one, itself.
+//
+// The clauses are relaxed by `relaxed_solver`.  Each round takes a model of the solver, grows the clauses
+// it satisfies into a maximal subset by enforcing the others one at a time, and then requires every later
+// model to satisfy one of the clauses outside that subset, so the same subset (or a smaller one) cannot
+// come up again.  The search ends when no model is left.
+fn maximal_satisfiable_subsets(clauses : &[Vec<Literal>]) -> Vec<Vec<usize>>
+{
+    let (mut solver, enforce) = relaxed_solver(clauses);
+    let satisfied = |m : &HashMap<char, bool>| -> Vec<usize> {
+        (0..clauses.len()).filter(|&i| clauses[i].iter().any(|l| m[&l.atom] != l.negated)).collect()
+    };
+
+    let mut subsets = Vec::new();
+    while let SatResult::Sat(m) = solver.solve()
+    {
+        let mut subset = satisfied(&m);
+        for i in 0..clauses.len()
+        {
+            if subset.contains(&i) {
+                continue;
+            }
+            let mut with : Vec<Literal> = subset.iter().map(|&j| enforce[j]).collect();
+            with.push(enforce[i]);
+            if let SatResult::Sat(m) = solver.solve_with(&with) {
+                subset = satisfied(&m);
+            }
+        }
+        let rest : Vec<Literal> = (0..clauses.len()).filter(|i| !subset.contains(i)).map(|i| enforce[i]).collect();
+        subsets.push(subset);
+        if rest.is_empty() {
+            break;
+        }
+        solver.add_clause(&rest);
+    }
+    subsets
+}
+
+// Every minimal correction set of `clauses`: a set of clauses whose removal leaves the rest satisfiable,
+// none of which can be put back.  These are exactly the complements of the maximal satisfiable subsets,
+// and are returned in the same order, as indices in increasing order.  A satisfiable clause set has just
+// the one, which is empty.
+fn minimal_correction_sets(clauses : &[Vec<Literal>]) -> Vec<Vec<usize>>
+{
+    maximal_satisfiable_subsets(clauses).into_iter().map(|subset| (0..clauses.len()).filter(|i| !subset.contains(i)).collect()).collect()
+}
+
+// E-graphs, for simplifying by equality saturation.  An e-graph holds many equivalent formulas at once:
+// each class is a set of equivalent nodes, and the children of a node are classes rather than nodes.
+// Rewriting only adds nodes and merges classes, so no rule can undo another and the order they are
+// applied in does not matter.
+
+// A node of an `EGraph`, over the ids of its children's classes.  Every connective is lowered to NOT and
+// two-input AND and OR.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum ENode {
+    True,
+    False,
+    Atom(char),
+    Not(usize),
+    And(usize, usize),
+    Or(usize, usize),
+}
+
+impl ENode
+{
+    fn children(self) -> Vec<usize>
+    {
+        match self
+        {
+            ENode::True | ENode::False | ENode::Atom(_) => vec!(),
+            ENode::Not(a) => vec!(a),
+            ENode::And(a, b) | ENode::Or(a, b) => vec!(a, b),
+        }
+    }
+
+    fn map<F : Fn(usize) -> usize>(self, f : F) -> ENode
+    {
+        match self
+        {
+            ENode::True | ENode::False | ENode::Atom(_) => self,
+            ENode::Not(a) => ENode::Not(f(a)),
+            ENode::And(a, b) => ENode::And(f(a), f(b)),
+            ENode::Or(a, b) => ENode::Or(f(a), f(b)),
+        }
+    }
+}
+
+// The right-hand side of a rewrite, built from existing classes.
+enum ETerm {
+    Class(usize),
+    True,
+    False,
+    Not(Box<ETerm>),
+    And(Box<ETerm>, Box<ETerm>),
+    Or(Box<ETerm>, Box<ETerm>),
+}
+
+// The most nodes `simplify_egraph` lets its e-graph grow to, and the most rounds of rewriting it runs.
+// Distribution and associativity keep finding new nodes long after anything smaller turns up.
+const EGRAPH_MAX_NODES : usize = 5_000;
+const EGRAPH_MAX_ROUNDS : usize = 12;
+
+struct EGraph
+{
+    // Union-find over class ids.
+    parent: Vec<usize>,
+    // The nodes of each class; empty for an id that is no longer canonical.
+    classes: Vec<Vec<ENode>>,
+    // Each node, with canonical children as of the last rebuild, to its class.
+    memo: HashMap<ENode, usize>,
+}
+
+impl EGraph
+{
+    fn new() -> EGraph
+    {
+        EGraph { parent: Vec::new(), classes: Vec::new(), memo: HashMap::new() }
+    }
+
+    // The number of nodes added so far, some of which may since have turned out to be the same.
+    fn node_count(&self) -> usize
+    {
+        self.parent.len()
+    }
+
+    fn find(&self, mut id : usize) -> usize
+    {
+        while self.parent[id] != id {
+            id = self.parent[id];
+        }
+        id
+    }
+
+    fn canonical(&self, n : ENode) -> ENode
+    {
+        n.map(|c| self.find(c))
+    }
+
+    // The nodes of the class of `id`.
+    fn class(&self, id : usize) -> &[ENode]
+    {
+        &self.classes[self.find(id)]
+    }
+
+    // The class of `n`, adding it in a class of its own if it is new.
+    fn add(&mut self, n : ENode) -> usize
+    {
+        let n = self.canonical(n);
+        if let Some(&id) = self.memo.get(&n) {
+            return self.find(id);
+        }
+        let id = self.parent.len();
+        self.parent.push(id);
+        self.classes.push(vec!(n));
+        self.memo.insert(n, id);
+        id
+    }
+
+    fn add_term(&mut self, t : &ETerm) -> usize
+    {
+        match *t
+        {
+            ETerm::Class(id) => self.find(id),
+            ETerm::True => self.add(ENode::True),
+            ETerm::False => self.add(ENode::False),
+            ETerm::Not(ref a) => { let a = self.add_term(a); self.add(ENode::Not(a)) }
+            ETerm::And(ref a, ref b) => { let (a, b) = (self.add_term(a), self.add_term(b)); self.add(ENode::And(a, b)) }
+            ETerm::Or(ref a, ref b) => { let (a, b) = (self.add_term(a), self.add_term(b)); self.add(ENode::Or(a, b)) }
+        }
+    }
+
+    // The class of `f`, lowering its connectives to NOT, AND and OR.
+    fn add_formula(&mut self, f : &Formula) -> usize
+    {
+        match *f
+        {
+            Formula::True => self.add(ENode::True),
+            Formula::False => self.add(ENode::False),
+            Formula::Atom(c) => self.add(ENode::Atom(c)),
+            Formula::Not(ref n) => { let n = self.add_formula(n); self.add(ENode::Not(n)) }
+            Formula::Implies { ref l, ref r } => {
+                let (l, r) = (self.add_formula(l), self.add_formula(r));
+                let not_l = self.add(ENode::Not(l));
+                self.add(ENode::Or(not_l, r))
+            }
+            Formula::Iff { ref l, ref r } => {
+                let (l, r) = (self.add_formula(l), self.add_formula(r));
+                self.add_same(&[l, r])
+            }
+            Formula::IffAll(ref v) => {
+                let ids : Vec<usize> = v.iter().map(|x| self.add_formula(x)).collect();
+                self.add_same(&ids)
+            }
+            Formula::And(ref v) => {
+                let ids : Vec<usize> = v.iter().map(|x| self.add_formula(x)).collect();
+                self.add_chain(&ids, true)
+            }
+            Formula::Or(ref v) => {
+                let ids : Vec<usize> = v.iter().map(|x| self.add_formula(x)).collect();
+                self.add_chain(&ids, false)
+            }
+            Formula::Annotated { ref inner, .. } => self.add_formula(inner),
+        }
+    }
+
+    // The AND (or OR) of `ids`, nested to the left; TRUE (or FALSE) if there are none.
+    fn add_chain(&mut self, ids : &[usize], and : bool) -> usize
+    {
+        match ids.split_first()
+        {
+            None => self.add(if and { ENode::True } else { ENode::False }),
+            Some((&first, rest)) => rest.iter().fold(first, |acc, &x| self.add(if and { ENode::And(acc, x) } else { ENode::Or(acc, x) })),
+        }
+    }
+
+    // The class saying that `ids` all have the same value: all true, or all false.
+    fn add_same(&mut self, ids : &[usize]) -> usize
+    {
+        let negated : Vec<usize> = ids.iter().map(|&x| self.add(ENode::Not(x))).collect();
+        let (all, none) = (self.add_chain(ids, true), self.add_chain(&negated, true));
+        self.add(ENode::Or(all, none))
+    }
+
+    // Merge the classes of `a` and `b`, returning false if they were already one.
+    fn union(&mut self, a : usize, b : usize) -> bool
+    {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return false;
+        }
+        self.parent[b] = a;
+        let moved = mem::take(&mut self.classes[b]);
+        self.classes[a].extend(moved);
+        true
+    }
+
+    // Restore the invariants after unions: give every node canonical children, and merge classes that
+    // now hold the same node (congruence), until no more merge.
+    fn rebuild(&mut self)
+    {
+        loop
+        {
+            self.memo.clear();
+            let mut merges = Vec::new();
+            for id in 0..self.classes.len()
+            {
+                let nodes = mem::take(&mut self.classes[id]);
+                let mut kept = Vec::new();
+                for n in nodes {
+                    let n = self.canonical(n);
+                    match self.memo.get(&n)
+                    {
+                        Some(&other) if other != id => merges.push((id, other)),
+                        Some(_) => {}
+                        None => { self.memo.insert(n, id); kept.push(n); }
+                    }
+                }
+                self.classes[id] = kept;
+            }
+            if merges.is_empty() {
+                break;
+            }
+            for (a, b) in merges {
+                self.union(a, b);
+            }
+        }
+    }
+
+    // The rewrites that apply to the node `n` of class `id`, as terms equal to it: double negation, De
+    // Morgan, constants, idempotence, complements, absorption, commutativity, associativity, distribution
+    // and its converse, factoring.
+    fn matches(&self, id : usize, n : ENode, out : &mut Vec<(usize, ETerm)>)
+    {
+        use self::ETerm::Class;
+
+        match n
+        {
+            ENode::True | ENode::False | ENode::Atom(_) => {}
+            ENode::Not(a) => {
+                for &m in self.class(a) {
+                    match m
+                    {
+                        ENode::True => out.push((id, ETerm::False)),
+                        ENode::False => out.push((id, ETerm::True)),
+                        ENode::Not(x) => out.push((id, Class(x))),
+                        ENode::And(x, y) => out.push((id, ETerm::Or(box ETerm::Not(box Class(x)), box ETerm::Not(box Class(y))))),
+                        ENode::Or(x, y) => out.push((id, ETerm::And(box ETerm::Not(box Class(x)), box ETerm::Not(box Class(y))))),
+                        ENode::Atom(_) => {}
+                    }
+                }
+            }
+            ENode::And(a, b) | ENode::Or(a, b) => {
+                let and = match n { ENode::And(..) => true, _ => false };
+                let (a, b) = (self.find(a), self.find(b));
+                // `same` builds this connective and `dual` the other; `unit` and `zero` are its identity and
+                // annihilator.  The comments below are written for AND.
+                let same = |x : ETerm, y : ETerm| if and { ETerm::And(box x, box y) } else { ETerm::Or(box x, box y) };
+                let dual = |x : ETerm, y : ETerm| if and { ETerm::Or(box x, box y) } else { ETerm::And(box x, box y) };
+                let (unit, zero) = if and { (ENode::True, ENode::False) } else { (ENode::False, ENode::True) };
+                let zero_term = || if and { ETerm::False } else { ETerm::True };
+
+                out.push((id, same(Class(b), Class(a))));
+                if a == b {
+                    out.push((id, Class(a)));
+                }
+                for &m in self.class(a)
+                {
+                    if m == unit { out.push((id, Class(b))); }
+                    if m == zero { out.push((id, zero_term())); }
+                    match (m, and)
+                    {
+                        (ENode::Not(x), _) if self.find(x) == b => out.push((id, zero_term())),
+                        (ENode::And(p, q), true) | (ENode::Or(p, q), false) => {
+                            out.push((id, same(Class(p), same(Class(q), Class(b)))));
+                        }
+                        (ENode::Or(p, q), true) | (ENode::And(p, q), false) => {
+                            out.push((id, dual(same(Class(p), Class(b)), same(Class(q), Class(b)))));
+                            // a AND (a OR y) = a, seen from the side of the OR.
+                            if self.find(p) == b || self.find(q) == b {
+                                out.push((id, Class(b)));
+                            }
+                            // (p OR q) AND (p OR s) = p OR (q AND s).
+                            for &k in self.class(b) {
+                                match (k, and)
+                                {
+                                    (ENode::Or(r, s), true) | (ENode::And(r, s), false) if self.find(r) == self.find(p) => {
+                                        out.push((id, dual(Class(p), same(Class(q), Class(s)))));
+                                    }
+                                    _ => {}
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    // One round of rewriting: every match found in the graph as it stands is added and merged in, until
+    // the graph reaches `EGRAPH_MAX_NODES`.  Returns false if nothing changed.
+    fn rewrite(&mut self) -> bool
+    {
+        let mut found = Vec::new();
+        for id in 0..self.classes.len() {
+            for &n in &self.classes[id] {
+                self.matches(id, n, &mut found);
+            }
+        }
+        let before = self.node_count();
+        let mut changed = false;
+        for (id, t) in found {
+            if self.node_count() >= EGRAPH_MAX_NODES {
+                break;
+            }
+            let other = self.add_term(&t);
+            changed |= self.union(id, other);
+        }
+        self.rebuild();
+        changed || self.node_count() != before
+    }
+
+    // The formula with the fewest nodes in the class of `root`.
+    fn extract(&self, root : usize) -> Formula
+    {
+        // best[id]: the cost of the cheapest node of class `id` found so far, and that node.
+        let mut best : Vec<Option<(usize, ENode)>> = vec!(None; self.classes.len());
+        let mut changed = true;
+        while changed
+        {
+            changed = false;
+            for id in 0..self.classes.len() {
+                for &n in &self.classes[id] {
+                    let costs : Option<Vec<usize>> = n.children().into_iter().map(|c| best[self.find(c)].map(|b| b.0)).collect();
+                    if let Some(costs) = costs {
+                        let cost = 1 + costs.iter().sum::<usize>();
+                        if best[id].map_or(true, |b| cost < b.0) {
+                            best[id] = Some((cost, n));
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        fn build(g : &EGraph, best : &[Option<(usize, ENode)>], id : usize) -> Formula
+        {
+            match best[g.find(id)].expect("every class has a finite formula").1
+            {
+                ENode::True => Formula::True,
+                ENode::False => Formula::False,
+                ENode::Atom(c) => Formula::Atom(c),
+                ENode::Not(a) => Formula::Not(box build(g, best, a)),
+                ENode::And(a, b) => Formula::And(vec!(build(g, best, a), build(g, best, b))),
+                ENode::Or(a, b) => Formula::Or(vec!(build(g, best, a), build(g, best, b))),
+            }
+        }
+
+        flatten(build(self, &best, root))
+    }
+}
+
+// `f` simplified by equality saturation: the boolean laws are applied to an e-graph of `f` until nothing
+// new turns up (or it reaches `EGRAPH_MAX_NODES` nodes or `EGRAPH_MAX_ROUNDS` rounds), and the smallest
+// formula in the class of `f` is read off.  Unlike the fixed order of passes in `simplify`, no rewrite can
+// block a later one.  The result of `simplify` goes into the graph too, and is returned if the graph finds
+// nothing smaller, so this is never larger than `simplify`.
+fn simplify_egraph(f : &Formula) -> Formula
+{
+    let simplified = simplify(f.clone());
+    let mut g = EGraph::new();
+    let root = g.add_formula(f);
+    let seed = g.add_formula(&simplified);
+    g.union(root, seed);
+    g.rebuild();
+    for _ in 0..EGRAPH_MAX_ROUNDS {
+        if g.node_count() >= EGRAPH_MAX_NODES || !g.rewrite() {
+            break;
+        }
+    }
+    let best = g.extract(root);
+    if size(&simplified) < size(&best) { simplified } else { best }
+}
+
+// And-inverter graphs.  Every connective is lowered to two-input ANDs with optionally inverted edges, and
+// structural hashing makes equal subgraphs a single node, so formulas that repeat structure (or that are
+// lowered into the same graph) share it.
+
+// An edge to a node of an `Aig`: the node index times two, plus one if the edge is inverted.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+struct AigEdge(u32);
+
+impl AigEdge
+{
+    fn node(self) -> usize
+    {
+        (self.0 >> 1) as usize
+    }
+
+    fn is_inverted(self) -> bool
+    {
+        self.0 & 1 == 1
+    }
+
+    fn invert(self) -> AigEdge
+    {
+        AigEdge(self.0 ^ 1)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum AigNode {
+    // Node 0 only; an inverted edge to it is true.
+    False,
+    Input(char),
+    And(AigEdge, AigEdge),
+}
+
+#[derive(Clone, Debug)]
+struct Aig
+{
+    nodes: Vec<AigNode>,
+    inputs: HashMap<char, AigEdge>,
+    // (a, b) with a <= b, to the edge of their AND.
+    strash: HashMap<(AigEdge, AigEdge), AigEdge>,
+    root: AigEdge,
+}
+
+const AIG_FALSE : AigEdge = AigEdge(0);
+const AIG_TRUE : AigEdge = AigEdge(1);
+
+impl Aig
+{
+    // An empty graph whose root is FALSE.
+    fn new() -> Aig
+    {
+        Aig { nodes: vec!(AigNode::False), inputs: HashMap::new(), strash: HashMap::new(), root: AIG_FALSE }
+    }
+
+    // The number of AND nodes.
+    fn node_count(&self) -> usize
+    {
+        self.nodes.iter().filter(|n| match n { &&AigNode::And(..) => true, _ => false }).count()
+    }
+
+    fn root(&self) -> AigEdge
+    {
+        self.root
+    }
+
+    fn input(&mut self, atom : char) -> AigEdge
+    {
+        if let Some(&e) = self.inputs.get(&atom) {
+            return e;
+        }
+        let e = AigEdge(2 * self.nodes.len() as u32);
+        self.nodes.push(AigNode::Input(atom));
+        self.inputs.insert(atom, e);
+        e
+    }
+
+    // The AND of two edges, folding constants, `x AND x` and `x AND ~x`, and reusing an existing node for
+    // the same pair.
+    fn and(&mut self, a : AigEdge, b : AigEdge) -> AigEdge
+    {
+        let (a, b) = if a <= b { (a, b) } else { (b, a) };
+        if a == AIG_FALSE || a == b.invert() {
+            return AIG_FALSE;
+        }
+        if a == AIG_TRUE || a == b {
+            return b;
+        }
+        if let Some(&e) = self.strash.get(&(a, b)) {
+            return e;
+        }
+        let e = AigEdge(2 * self.nodes.len() as u32);
+        self.nodes.push(AigNode::And(a, b));
+        self.strash.insert((a, b), e);
+        e
+    }
+
+    fn or(&mut self, a : AigEdge, b : AigEdge) -> AigEdge
+    {
+        self.and(a.invert(), b.invert()).invert()
+    }
+
+    // Add the graph of `f`, returning its edge.  The root is left alone.
+    fn lower(&mut self, f : &Formula) -> AigEdge
+    {
+        match f
+        {
+            &Formula::True => AIG_TRUE,
+            &Formula::False => AIG_FALSE,
+            &Formula::Atom(c) => self.input(c),
+            &Formula::Not(ref n) => self.lower(n).invert(),
+            &Formula::Annotated { ref inner, .. } => self.lower(inner),
+            &Formula::Implies { ref l, ref r } => {
+                let (l, r) = (self.lower(l), self.lower(r));
+                self.or(l.invert(), r)
+            }
+            // l <-> r ~> ~(l ^ ~r) ^ ~(r ^ ~l)
+            &Formula::Iff { ref l, ref r } => {
+                let (l, r) = (self.lower(l), self.lower(r));
+                let (a, b) = (self.and(l, r.invert()), self.and(r, l.invert()));
+                self.and(a.invert(), b.invert())
+            }
+            &Formula::IffAll(ref v) => {
+                let edges : Vec<AigEdge> = v.iter().map(|x| self.lower(x)).collect();
+                let all = edges.iter().fold(AIG_TRUE, |acc, &e| self.and(acc, e));
+                let none = edges.iter().fold(AIG_TRUE, |acc, &e| self.and(acc, e.invert()));
+                self.or(all, none)
+            }
+            &Formula::And(ref v) => v.iter().fold(AIG_TRUE, |acc, x| { let e = self.lower(x); self.and(acc, e) }),
+            &Formula::Or(ref v) => v.iter().fold(AIG_FALSE, |acc, x| { let e = self.lower(x); self.or(acc, e) }),
+        }
+    }
+
+    // The formula of an edge, using only AND and NOT.  Shared nodes are written out once per use, so this
+    // can be much bigger than the graph.
+    fn formula(&self, e : AigEdge) -> Formula
+    {
+        let f = match self.nodes[e.node()]
+        {
+            AigNode::False => Formula::False,
+            AigNode::Input(c) => Formula::Atom(c),
+            AigNode::And(a, b) => Formula::And(vec!(self.formula(a), self.formula(b))),
+        };
+        if !e.is_inverted() {
+            f
+        } else if f == Formula::False {
+            Formula::True
+        } else {
+            Formula::Not(box f)
+        }
+    }
+}
+
+impl Aig
+{
+    // A clause encoding of the graph, with one literal per node: the atom of an input, a fresh atom (taken
+    // from `used`) defined equal to the AND of an AND node, and for the FALSE node a fresh atom forced
+    // false.
+    fn clauses(&self, used : &mut BTreeSet<char>) -> (Vec<Vec<Literal>>, Vec<Literal>)
+    {
+        let mut clauses = Vec::new();
+        let mut lits : Vec<Literal> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes
+        {
+            let lit = match node
+            {
+                &AigNode::Input(c) => Literal { atom: c, negated: false },
+                _ => Literal { atom: fresh_atom(used), negated: false },
+            };
+            let edge = |e : AigEdge| if e.is_inverted() { lits[e.node()].negate() } else { lits[e.node()] };
+            match node
+            {
+                &AigNode::False => clauses.push(vec!(lit.negate())),
+                &AigNode::Input(_) => {}
+                // x <-> a ^ b ~> (~x v a) ^ (~x v b) ^ (x v ~a v ~b)
+                &AigNode::And(a, b) => {
+                    let (a, b) = (edge(a), edge(b));
+                    clauses.push(vec!(lit.negate(), a));
+                    clauses.push(vec!(lit.negate(), b));
+                    clauses.push(vec!(lit, a.negate(), b.negate()));
+                }
+            }
+            lits.push(lit);
+        }
+        (clauses, lits)
+    }
+}
+
+// The and-inverter graph of `f`, with its root set to the edge for `f`.
+fn to_aig(f : &Formula) -> Aig
+{
+    let mut aig = Aig::new();
+    aig.root = aig.lower(f);
+    aig
+}
+
+// A gate of a `Netlist`, driving the wire `out`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Gate {
+    Const { out: String, value: bool },
+    Not { out: String, a: String },
+    And { out: String, a: String, b: String },
+}
+
+// A formula as a circuit of primitive gates over named wires, e.g. for a hardware toolchain.  The inputs
+// are wires named after the atoms, and the gates are in topological order.
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Netlist
+{
+    inputs: Vec<(char, String)>,
+    gates: Vec<Gate>,
+    output: String,
+}
+
+impl Netlist
+{
+    // The value of the output wire under `env`, found by simulating the gates in order.
+    fn eval(&self, env : &HashMap<char, bool>) -> bool
+    {
+        let mut wires : HashMap<&str, bool> = self.inputs.iter().map(|&(c, ref w)| (&w[..], env[&c])).collect();
+        for gate in &self.gates
+        {
+            let (out, value) = match *gate
+            {
+                Gate::Const { ref out, value } => (out, value),
+                Gate::Not { ref out, ref a } => (out, !wires[&a[..]]),
+                Gate::And { ref out, ref a, ref b } => (out, wires[&a[..]] && wires[&b[..]]),
+            };
+            wires.insert(out, value);
+        }
+        wires[&self.output[..]]
+    }
+
+    // A structural Verilog module `formula` with one input port per input wire and the output port `out`,
+    // using the `and` and `not` primitives.
+    fn to_verilog(&self) -> String
+    {
+        let mut ports : Vec<String> = self.inputs.iter().map(|(_, w)| format!("input {}", w)).collect();
+        ports.push("output out".to_string());
+        let mut s = format!("module formula({});\n", ports.join(", "));
+        for gate in &self.gates
+        {
+            match *gate
+            {
+                Gate::Const { ref out, .. } | Gate::Not { ref out, .. } | Gate::And { ref out, .. } => s.push_str(&format!("  wire {};\n", out)),
+            }
+        }
+        for gate in &self.gates
+        {
+            match *gate
+            {
+                Gate::Const { ref out, value } => s.push_str(&format!("  assign {} = 1'b{};\n", out, value as u8)),
+                Gate::Not { ref out, ref a } => s.push_str(&format!("  not ({}, {});\n", out, a)),
+                Gate::And { ref out, ref a, ref b } => s.push_str(&format!("  and ({}, {}, {});\n", out, a, b)),
+            }
+        }
+        s.push_str(&format!("  assign out = {};\nendmodule\n", self.output));
+        s
+    }
+}
+
+// The netlist of `f`, from its and-inverter graph: an AND gate per AND node that the root depends on, and
+// a NOT gate per inverted edge used.  The input wires are named by `ascii_name`.
+fn to_netlist(f : &Formula) -> Netlist
+{
+    let aig = to_aig(f);
+    let inputs : Vec<(char, String)> = atoms(f).into_iter().map(|c| (c, ascii_name(c))).collect();
+    let mut names : Vec<String> = aig.nodes.iter().enumerate().map(|(i, n)| match n
+    {
+        &AigNode::Input(c) => inputs.iter().find(|x| x.0 == c).expect("every input is an atom of the formula").1.clone(),
+        _ => format!("n{}", i),
+    }).collect();
+
+    // Mark the nodes the root depends on; children have lower indices than their parents.
+    let mut needed = vec!(false; aig.nodes.len());
+    needed[aig.root().node()] = true;
+    for i in (0..aig.nodes.len()).rev() {
+        if let (true, &AigNode::And(a, b)) = (needed[i], &aig.nodes[i]) {
+            needed[a.node()] = true;
+            needed[b.node()] = true;
+        }
+    }
+
+    let mut gates = Vec::new();
+    let mut inverted : HashMap<usize, String> = HashMap::new();
+    let mut wire = |e : AigEdge, gates : &mut Vec<Gate>, names : &[String]| -> String {
+        if !e.is_inverted() {
+            return names[e.node()].clone();
+        }
+        inverted.entry(e.node()).or_insert_with(|| {
+            let out = format!("{}_n", names[e.node()]);
+            gates.push(Gate::Not { out: out.clone(), a: names[e.node()].clone() });
+            out
+        }).clone()
+    };
+    for i in (0..aig.nodes.len()).filter(|&i| needed[i])
+    {
+        match aig.nodes[i]
+        {
+            AigNode::False => {
+                names[i] = "zero".to_string();
+                gates.push(Gate::Const { out: names[i].clone(), value: false });
+            }
+            AigNode::Input(_) => {}
+            AigNode::And(a, b) => {
+                let (a, b) = (wire(a, &mut gates, &names), wire(b, &mut gates, &names));
+                gates.push(Gate::And { out: names[i].clone(), a, b });
+            }
+        }
+    }
+    let output = wire(aig.root(), &mut gates, &names);
+    Netlist { inputs, gates, output }
+}
+
+// Equivalence checked with the SAT solver instead of a truth table: both formulas are lowered into one
+// and-inverter graph, so the structure they share is encoded once, and the miter (the two outputs
+// differing) is tested for satisfiability.  An atom in only one formula is free in the other.
+fn equivalent_by_miter(a : &Formula, b : &Formula) -> bool
+{
+    let mut aig = Aig::new();
+    let (ea, eb) = (aig.lower(a), aig.lower(b));
+    if ea == eb {
+        return true;
+    }
+    let mut used : BTreeSet<char> = shared_atoms(a, b).into_iter().collect();
+    let (mut clauses, lits) = aig.clauses(&mut used);
+    let edge = |e : AigEdge| if e.is_inverted() { lits[e.node()].negate() } else { lits[e.node()] };
+    clauses.push(vec!(edge(ea), edge(eb)));
+    clauses.push(vec!(edge(ea).negate(), edge(eb).negate()));
+    Solver::from_clauses(&clauses).solve() == SatResult::Unsat
+}
+
+// The number of assignments `group_equivalent` samples for its semantic hash, one bit of the hash each.
+const SEMANTIC_HASH_SAMPLES : u64 = 64;
+
+// Indices into `formulas` grouped by logical equivalence: each group is in increasing order, and the groups
+// are in order of their first member.  Each formula is first hashed by its values on a fixed sample of
+// pseudo-random assignments to all the atoms, so only formulas with equal hashes can be equivalent, and
+// each is then compared with `equivalent_by_miter` against the first member of each group in its bucket.
+fn group_equivalent(formulas : Vec<Formula>) -> Vec<Vec<usize>>
+{
+    let atoms : BTreeSet<char> = formulas.iter().flat_map(|f| atoms(f)).collect();
+    let samples : Vec<HashMap<char, bool>> = (0..SEMANTIC_HASH_SAMPLES).map(|k| {
+        atoms.iter().map(|&c| {
+            let mut hasher = DefaultHasher::new();
+            (c, k).hash(&mut hasher);
+            (c, hasher.finish() & 1 == 1)
+        }).collect()
+    }).collect();
+
+    let mut groups : Vec<Vec<usize>> = Vec::new();
+    // The groups whose formulas have each hash.
+    let mut buckets : HashMap<u64, Vec<usize>> = HashMap::new();
+    for (i, f) in formulas.iter().enumerate()
+    {
+        let hash = samples.iter().enumerate().fold(0u64, |h, (k, env)| h | (eval(f, env) as u64) << k);
+        let bucket = buckets.entry(hash).or_insert_with(Vec::new);
+        match bucket.iter().find(|&&g| equivalent_by_miter(&formulas[groups[g][0]], f))
+        {
+            Some(&g) => groups[g].push(i),
+            None => {
+                bucket.push(groups.len());
+                groups.push(vec!(i));
+            }
+        }
+    }
+    groups
+}
+
+// A clause encoding of `f` through its and-inverter graph, with the literal that is equal to `f`.  The
+// auxiliary atoms are fresh ones taken from `used`.
+fn circuit_clauses(f : &Formula, used : &mut BTreeSet<char>) -> (Vec<Vec<Literal>>, Literal)
+{
+    let aig = to_aig(f);
+    let (clauses, lits) = aig.clauses(used);
+    let root = aig.root();
+    let lit = if root.is_inverted() { lits[root.node()].negate() } else { lits[root.node()] };
+    (clauses, lit)
+}
+
+// A smallest-by-inclusion part of a model of `f` that still forces `f` true whatever the other atoms are,
+// i.e. a prime implicant contained in the model.  Atoms are dropped greedily in sorted order while the
+// rest still implies `f`, which is checked with the solver.  Panics if `model` does not satisfy `f`.
+fn minimal_support(f : &Formula, model : &HashMap<char, bool>) -> HashMap<char, bool>
+{
+    assert!(eval(f, model), "minimal_support: the assignment does not satisfy the formula");
+    let atoms = atoms(f);
+    let mut used : BTreeSet<char> = atoms.iter().cloned().collect();
+    let (mut clauses, root) = circuit_clauses(f, &mut used);
+    clauses.push(vec!(root.negate()));
+    let mut counter = Solver::from_clauses(&clauses);
+
+    let cube : Vec<Literal> = atoms.iter().map(|&c| Literal { atom: c, negated: !model[&c] }).collect();
+    shrink_implicant(&mut counter, cube).into_iter().map(|l| (l.atom, !l.negated)).collect()
+}
+
+// Drop literals from `cube` one at a time, in order, while it stays unsatisfiable together with the
+// clauses of `counter`.  When those encode ~f this shrinks an implicant of f to a prime one.
+fn shrink_implicant(counter : &mut Solver, cube : Vec<Literal>) -> Vec<Literal>
+{
+    let mut cube = cube;
+    let mut i = 0;
+    while i < cube.len() {
+        let mut smaller = cube.clone();
+        smaller.remove(i);
+        if counter.solve_with(&smaller) == SatResult::Unsat { cube = smaller; } else { i += 1; }
+    }
+    cube
+}
+
+// All prime implicants of `f`, each sorted, in sorted order, found with the SAT solver.  One solver holds
+// a clause encoding of ~f, so a cube is an implicant exactly when it makes that unsatisfiable.  A second
+// solver searches for candidate cubes, with two selector atoms per atom of `f` (for its positive and
+// negative literal).  A candidate that is not an implicant yields a model of ~f, and every implicant has to
+// contradict that model, which gives a clause over the selectors.  A candidate that is an implicant is
+// shrunk to a prime one by dropping literals while it stays an implicant, and every cube containing the
+// prime is then blocked.  Selectors are decided false first, so candidates start small.
+fn enumerate_prime_implicants_sat(f : &Formula) -> Vec<Vec<Literal>>
+{
+    let atoms = atoms(f);
+    let mut used : BTreeSet<char> = atoms.iter().cloned().collect();
+    let (mut clauses, root) = circuit_clauses(f, &mut used);
+    clauses.push(vec!(root.negate()));
+    let mut counter = Solver::from_clauses(&clauses);
+
+    // select[i] = (selects atoms[i], selects ~atoms[i]).
+    let select : Vec<(Literal, Literal)> = atoms.iter().map(|_| {
+        (Literal { atom: fresh_atom(&mut used), negated: false }, Literal { atom: fresh_atom(&mut used), negated: false })
+    }).collect();
+    let mut cubes = Solver::new();
+    for &(p, n) in &select {
+        cubes.add_clause(&[p.negate(), n.negate()]);
+    }
+
+    let mut primes = Vec::new();
+    while let SatResult::Sat(m) = cubes.solve()
+    {
+        let mut cube : Vec<Literal> = Vec::new();
+        for (i, &(p, n)) in select.iter().enumerate() {
+            if m[&p.atom] { cube.push(Literal { atom: atoms[i], negated: false }); }
+            if m[&n.atom] { cube.push(Literal { atom: atoms[i], negated: true }); }
+        }
+        match counter.solve_with(&cube)
+        {
+            SatResult::Sat(cm) => {
+                // Some literal of every implicant is false in `cm`.  (An atom the graph folded away is
+                // missing from it, and may as well be false.)
+                let clause : Vec<Literal> = atoms.iter().zip(&select).map(|(c, &(p, n))| if cm.get(c) == Some(&true) { n } else { p }).collect();
+                cubes.add_clause(&clause);
+            }
+            SatResult::Unsat => {
+                cube = shrink_implicant(&mut counter, cube);
+                let block : Vec<Literal> = cube.iter().map(|l| {
+                    let i = atoms.binary_search(&l.atom).unwrap();
+                    if l.negated { select[i].1.negate() } else { select[i].0.negate() }
+                }).collect();
+                cubes.add_clause(&block);
+                cube.sort();
+                primes.push(cube);
+            }
+        }
+    }
+    primes.sort();
+    primes
+}
+
+// The essential prime implicants of `f`: those that are the only prime implicant true on some model of
+// `f`, so that every smallest DNF of `f` has to contain them.  Sorted like
+// `enumerate_prime_implicants_sat`.
+fn essential_prime_implicants(f : &Formula) -> Vec<Vec<Literal>>
+{
+    let primes = enumerate_prime_implicants_sat(f);
+    let mut essential = vec!(false; primes.len());
+    for env in all_sat(f)
+    {
+        let mut covering = primes.iter().enumerate().filter(|&(_, p)| p.iter().all(|l| env[&l.atom] != l.negated)).map(|(i, _)| i);
+        if let (Some(i), None) = (covering.next(), covering.next()) {
+            essential[i] = true;
+        }
+    }
+    primes.into_iter().zip(essential).filter(|&(_, e)| e).map(|(p, _)| p).collect()
+}
+
+// A smallest DNF over `atoms` of an incompletely specified function: true on the rows listed in `on_set`,
+// false on those in neither set and free on the don't-care rows of `dc_set`, with rows numbered as by
+// `assignment`.  As few terms as possible, and among those as few literals.  The terms are prime
+// implicants of the function that is true on both sets, chosen to cover the on-set rows.  Panics on more
+// than `FROM_FN_MAX_ATOMS` atoms or a row out of range.
+fn minimize_incomplete(on_set : &[u32], dc_set : &[u32], atoms : &[char]) -> Formula
+{
+    let rows = 1u64 << atoms.len();
+    assert!(on_set.iter().chain(dc_set).all(|&r| (r as u64) < rows), "minimize_incomplete: row out of range for {} atoms", atoms.len());
+    let allowed : BTreeSet<u64> = on_set.iter().chain(dc_set).map(|&r| r as u64).collect();
+    let loose = from_fn(atoms, |env| allowed.contains(&atoms.iter().fold(0, |row, c| row << 1 | env[c] as u64)));
+    let primes = enumerate_prime_implicants_sat(&loose);
+
+    // covers[i][k]: term i is true on row on_set[k].
+    let covers : Vec<Vec<bool>> = primes.iter().map(|p| {
+        on_set.iter().map(|&r| assignment(atoms, r as u64)).map(|env| p.iter().all(|l| env[&l.atom] != l.negated)).collect()
+    }).collect();
+    let cost : Vec<(usize, usize)> = primes.iter().map(|p| (1, p.len())).collect();
+    let chosen = smallest_cover(on_set.len(), &covers, &cost);
+    let terms : Vec<Vec<Literal>> = chosen.into_iter().map(|i| primes[i].clone()).collect();
+    dnf_formula(&terms)
+}
+
+// Every model of `f`, as assignments to its atoms, found with the SAT solver: after each model a clause
+// ruling it out is added and the solver is run again, so the work grows with the number of models instead
+// of with the size of the truth table.  The models come in no particular order.
+fn all_sat(f : &Formula) -> Vec<HashMap<char, bool>>
+{
+    projected_models(f, &atoms(f))
+}
+
+// The number of assignments to `keep` that extend to a model of `f`.  Atoms of `keep` that are not in
+// `f` can take either value.
+fn count_models_projected(f : &Formula, keep : &[char]) -> u64
+{
+    let mut keep = keep.to_vec();
+    keep.sort();
+    keep.dedup();
+    projected_models(f, &keep).len() as u64
+}
+
+// The distinct restrictions of the models of `f` to `keep`, enumerated as in `all_sat` but blocking only
+// the kept atoms' values.
+fn projected_models(f : &Formula, keep : &[char]) -> Vec<HashMap<char, bool>>
+{
+    let mut models = Vec::new();
+    for_each_projected_model(f, keep, None, |m| { models.push(m.clone()); ControlFlow::Continue(()) });
+    models
+}
+
+// Call `callback` on each model of `f` in turn, as `all_sat` finds them, without keeping them.  Stops
+// when the callback returns `Break`, after `limit` models, or when there are no more.
+fn for_each_model<F: FnMut(&HashMap<char, bool>) -> ControlFlow<()>>(f : &Formula, limit : Option<usize>, callback : F)
+{
+    for_each_projected_model(f, &atoms(f), limit, callback)
+}
+
+fn for_each_projected_model<F: FnMut(&HashMap<char, bool>) -> ControlFlow<()>>(f : &Formula, keep : &[char], limit : Option<usize>, mut callback : F)
+{
+    let mut used : BTreeSet<char> = atoms(f).into_iter().chain(keep.iter().cloned()).collect();
+    let (mut clauses, root) = circuit_clauses(f, &mut used);
+    clauses.push(vec!(root));
+    let mut solver = Solver::from_clauses(&clauses);
+    // Atoms that no clause mentions still need a value in each model.
+    for &c in keep {
+        solver.var(c);
+    }
+
+    let mut count = 0;
+    while limit.map_or(true, |l| count < l)
+    {
+        let model : HashMap<char, bool> = match solver.solve()
+        {
+            SatResult::Sat(m) => keep.iter().map(|&c| (c, m[&c])).collect(),
+            SatResult::Unsat => return,
+        };
+        count += 1;
+        if let ControlFlow::Break(()) = callback(&model) {
+            return;
+        }
+        let block : Vec<Literal> = keep.iter().map(|&c| Literal { atom: c, negated: model[&c] }).collect();
+        solver.add_clause(&block);
+    }
+}
+
+// The atoms that have the same value in every model of `f`, with that value; empty if `f` is
+// unsatisfiable.  Each atom that has kept its value in every model found so far is tested by asking for a
+// model with the opposite value, and each such model also rules out any other candidate it flips.
+fn backbone(f : &Formula) -> HashMap<char, bool>
+{
+    let mut used : BTreeSet<char> = atoms(f).into_iter().collect();
+    let (mut clauses, root) = circuit_clauses(f, &mut used);
+    clauses.push(vec!(root));
+    let mut solver = Solver::from_clauses(&clauses);
+    let first = match solver.solve()
+    {
+        SatResult::Sat(m) => m,
+        SatResult::Unsat => return HashMap::new(),
+    };
+
+    // An atom the solver never saw is unconstrained, so it is not a candidate.
+    let mut candidates : HashMap<char, bool> = atoms(f).into_iter().filter_map(|c| first.get(&c).map(|&v| (c, v))).collect();
+    let mut backbone = HashMap::new();
+    for c in atoms(f)
+    {
+        let value = match candidates.get(&c) { Some(&v) => v, None => continue };
+        match solver.solve_with(&[Literal { atom: c, negated: value }])
+        {
+            SatResult::Unsat => { backbone.insert(c, value); }
+            SatResult::Sat(m) => candidates.retain(|a, v| m[a] == *v),
+        }
+    }
+    backbone
+}
+
+// The pairs of atoms (a, b, same), a < b, that are equal (same) or opposite (not same) in every model of
+// `f`, found as `backbone` does: each model found rules out the pairs it breaks, and the rest are tested
+// by asking for a model that breaks them.  Empty if `f` is unsatisfiable.
+fn detect_equivalences(f : &Formula) -> Vec<(char, char, bool)>
+{
+    let mut used : BTreeSet<char> = atoms(f).into_iter().collect();
+    let (mut clauses, root) = circuit_clauses(f, &mut used);
+    clauses.push(vec!(root));
+    let mut solver = Solver::from_clauses(&clauses);
+    let first = match solver.solve()
+    {
+        SatResult::Sat(m) => m,
+        SatResult::Unsat => return Vec::new(),
+    };
+
+    // An atom the solver never saw is unconstrained, so it is in no pair.
+    let seen : Vec<char> = atoms(f).into_iter().filter(|c| first.contains_key(c)).collect();
+    let mut candidates : Vec<(char, char, bool)> = Vec::new();
+    for (i, &a) in seen.iter().enumerate() {
+        for &b in &seen[i + 1..] {
+            candidates.push((a, b, first[&a] == first[&b]));
+        }
+    }
+
+    let mut found = Vec::new();
+    while let Some((a, b, same)) = candidates.pop()
+    {
+        // Either way of breaking the relation: a true, or a false.
+        let mut broken = None;
+        for &va in &[true, false] {
+            let vb = if same { !va } else { va };
+            if let SatResult::Sat(m) = solver.solve_with(&[Literal { atom: a, negated: !va }, Literal { atom: b, negated: !vb }]) {
+                broken = Some(m);
+                break;
+            }
+        }
+        match broken
+        {
+            None => found.push((a, b, same)),
+            Some(m) => candidates.retain(|&(x, y, s)| (m[&x] == m[&y]) == s),
+        }
+    }
+    found.sort();
+    found
+}
+
+// `f` with every atom that `detect_equivalences` finds equal or opposite to an earlier atom replaced by
+// the earliest atom of its class (negated where they are opposite).  The result is equisatisfiable with
+// `f` and mentions fewer atoms; a model of it extends to one of `f` by giving each replaced atom the value
+// of its replacement.
+fn substitute_equivalences(f : Formula) -> Formula
+{
+    let mut replaced = BTreeSet::new();
+    let mut g = f.clone();
+    // The pairs are sorted, so the first pair mentioning b pairs it with the least atom of its class,
+    // which is never replaced itself.
+    for (a, b, same) in detect_equivalences(&f)
+    {
+        if replaced.insert(b) {
+            let rep = if same { Formula::Atom(a) } else { Formula::Not(box Formula::Atom(a)) };
+            g = substitute(&g, b, &rep);
+        }
+    }
+    g
+}
+
+// A parity constraint: the XOR of `atoms` is `parity` (true for an odd number of true atoms).
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct XorClause
+{
+    atoms: Vec<char>,
+    parity: bool,
+}
+
+// The parity constraints among the top-level conjuncts of `f`: those built only from atoms, constants,
+// negation, <-> and two-argument IFF(...), which all stand for affine functions over GF(2)
+// (l <-> r is l XOR r XOR TRUE).  Other conjuncts are skipped.  The atoms of each clause are sorted, and
+// an atom occurring an even number of times cancels out.
+fn extract_xor_clauses(f : &Formula) -> Vec<XorClause>
+{
+    // g as (atoms, c) standing for XOR(atoms) XOR c.
+    fn affine(g : &Formula) -> Option<(BTreeSet<char>, bool)>
+    {
+        let xor = |l : &Formula, r : &Formula| -> Option<(BTreeSet<char>, bool)> {
+            let (a, ca) = affine(l)?;
+            let (b, cb) = affine(r)?;
+            Some((a.symmetric_difference(&b).cloned().collect(), ca != cb))
+        };
+        match g
+        {
+            &Formula::True => Some((BTreeSet::new(), true)),
+            &Formula::False => Some((BTreeSet::new(), false)),
+            &Formula::Atom(c) => Some((vec!(c).into_iter().collect(), false)),
+            &Formula::Not(ref n) => affine(n).map(|(a, c)| (a, !c)),
+            &Formula::Iff { ref l, ref r } => xor(l, r).map(|(a, c)| (a, !c)),
+            &Formula::IffAll(ref v) if v.len() == 2 => xor(&v[0], &v[1]).map(|(a, c)| (a, !c)),
+            &Formula::IffAll(ref v) if v.len() < 2 => Some((BTreeSet::new(), true)),
+            _ => None,
+        }
+    }
+
+    fn conjuncts<'a>(f : &'a Formula, out : &mut Vec<&'a Formula>)
+    {
+        match f
+        {
+            &Formula::And(ref v) => for x in v { conjuncts(x, out); },
+            _ => out.push(f),
+        }
+    }
+
+    let mut all = Vec::new();
+    conjuncts(f, &mut all);
+    // XOR(atoms) XOR c is true exactly when XOR(atoms) is ~c.
+    all.into_iter().filter_map(affine).map(|(a, c)| XorClause { atoms: a.into_iter().collect(), parity: !c }).collect()
+}
+
+// A solution of a system of parity constraints by Gaussian elimination over GF(2), assigning every atom
+// of the system (free ones false), or None if the system is inconsistent.
+fn solve_xor(clauses : &[XorClause]) -> Option<HashMap<char, bool>>
+{
+    let atoms : Vec<char> = clauses.iter().flat_map(|c| c.atoms.iter().cloned()).collect::<BTreeSet<char>>().into_iter().collect();
+    let words = atoms.len().div_ceil(64);
+    // Each row is the atoms' coefficients as a bitset, then the parity.
+    let mut rows : Vec<(Vec<u64>, bool)> = clauses.iter().map(|c| {
+        let mut bits = vec!(0u64; words);
+        for a in &c.atoms {
+            let i = atoms.binary_search(a).unwrap();
+            bits[i / 64] ^= 1 << (i % 64);
+        }
+        (bits, c.parity)
+    }).collect();
+
+    // Reduced row echelon form; pivots[k] is the column of row k's leading atom.
+    let mut pivots = Vec::new();
+    for col in 0..atoms.len()
+    {
+        let has = |r : &(Vec<u64>, bool)| r.0[col / 64] >> (col % 64) & 1 == 1;
+        let k = pivots.len();
+        let found = match (k..rows.len()).find(|&i| has(&rows[i])) { Some(i) => i, None => continue };
+        rows.swap(k, found);
+        let pivot = rows[k].clone();
+        for (i, row) in rows.iter_mut().enumerate() {
+            if i != k && has(row) {
+                for w in 0..words { row.0[w] ^= pivot.0[w]; }
+                row.1 ^= pivot.1;
+            }
+        }
+        pivots.push(col);
+    }
+    // A leftover row reads 0 = parity.
+    if rows[pivots.len()..].iter().any(|r| r.1) {
+        return None;
+    }
+
+    let mut model : HashMap<char, bool> = atoms.iter().map(|&a| (a, false)).collect();
+    for (k, &col) in pivots.iter().enumerate() {
+        model.insert(atoms[col], rows[k].1);
+    }
+    Some(model)
+}
+
+fn main() {
+    let nn = Formula::Not(box Formula::Not(box Formula::Atom('A')));
+    println!("{} simplifies to {}", nn, simplify(nn.clone()));
+
+    let example = Formula::Implies { l: box Formula::And(vec!(Formula::Atom('P'), Formula::Not(box Formula::Atom('Q')))), r: box Formula::Atom('R') };
+    println!("{} simplifies to {} and then to {}", example, simplify2(simplify1(example.clone())), simplify(example.clone()));
+
+    let another = Formula::Iff { l: box Formula::Or(vec!(Formula::Atom('P'), Formula::Atom('Q'))), r: box Formula::Atom('R') };
+    println!("{} simplifies to {} and then to {}", another, simplify2(simplify1(another.clone())), simplify(another.clone()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(s : &str) -> Formula
+    {
+        parse(s).unwrap()
+    }
+
+    fn lit(s : &str) -> Literal
+    {
+        let c = s.chars().last().unwrap();
+        Literal { atom: c, negated: s.starts_with('~') }
+    }
+
+    // A clause list written as space-separated literals, one clause per element.
+    fn cnf(clauses : &[&str]) -> Vec<Vec<Literal>>
+    {
+        clauses.iter().map(|c| c.split_whitespace().map(lit).collect()).collect()
+    }
+
+    fn env(pairs : &[(char, bool)]) -> HashMap<char, bool>
+    {
+        pairs.iter().cloned().collect()
+    }
+
+    fn satisfies(clauses : &[Vec<Literal>], model : &HashMap<char, bool>) -> bool
+    {
+        clauses.iter().all(|c| c.iter().any(|l| model.get(&l.atom).map_or(false, |&v| v != l.negated)))
+    }
+
+    // The truth table by brute force, for comparing against the faster methods.
+    fn brute_sat(clauses : &[Vec<Literal>]) -> bool
+    {
+        let mut atoms : Vec<char> = clauses.iter().flat_map(|c| c.iter().map(|l| l.atom)).collect();
+        atoms.sort();
+        atoms.dedup();
+        (0..(1u64 << atoms.len())).any(|row| satisfies(clauses, &assignment(&atoms, row)))
+    }
+
+    // A small deterministic generator of random clause sets.
+    struct Rng(u64);
+
+    impl Rng
+    {
+        fn below(&mut self, n : u64) -> u64
+        {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+
+        fn clauses(&mut self, atoms : u64, count : usize, width : u64) -> Vec<Vec<Literal>>
+        {
+            (0..count).map(|_| (0..width).map(|_| Literal {
+                atom: (b'A' + self.below(atoms) as u8) as char,
+                negated: self.below(2) == 1,
+            }).collect()).collect()
+        }
+
+        fn formula(&mut self, atoms : u64, depth : usize) -> Formula
+        {
+            let atom = Formula::Atom((b'A' + self.below(atoms) as u8) as char);
+            if depth == 0 {
+                return atom;
+            }
+            match self.below(7)
+            {
+                0 => atom,
+                1 => Formula::Not(box self.formula(atoms, depth - 1)),
+                2 => Formula::And(vec!(self.formula(atoms, depth - 1), self.formula(atoms, depth - 1))),
+                3 => Formula::Or(vec!(self.formula(atoms, depth - 1), self.formula(atoms, depth - 1), self.formula(atoms, depth - 1))),
+                4 => Formula::Implies { l: box self.formula(atoms, depth - 1), r: box self.formula(atoms, depth - 1) },
+                5 => Formula::Iff { l: box self.formula(atoms, depth - 1), r: box self.formula(atoms, depth - 1) },
+                _ => Formula::IffAll(vec!(self.formula(atoms, depth - 1), self.formula(atoms, depth - 1))),
+            }
+        }
+    }
+
+    // The prime implicants of `f` by brute force: every term over its atoms that entails it and stops
+    // doing so when any literal is dropped.
+    fn brute_prime_implicants(f : &Formula) -> Vec<Vec<Literal>>
+    {
+        let atoms = atoms(f);
+        let implies_f = |t : &[Literal]| entails(&and_of(t.iter().map(|&l| literal_formula(l)).collect()), f);
+        let mut primes = Vec::new();
+        for code in 0..3u32.pow(atoms.len() as u32)
+        {
+            let (mut code, mut term) = (code, Vec::new());
+            for &a in &atoms {
+                match code % 3 {
+                    1 => term.push(Literal { atom: a, negated: false }),
+                    2 => term.push(Literal { atom: a, negated: true }),
+                    _ => {}
+                }
+                code /= 3;
+            }
+            let prime = implies_f(&term) && (0..term.len()).all(|i| {
+                let mut shorter = term.clone();
+                shorter.remove(i);
+                !implies_f(&shorter)
+            });
+            if prime {
+                primes.push(term);
+            }
+        }
+        primes.sort();
+        primes
+    }
+
+    fn sorted<T : Ord>(mut v : Vec<T>) -> Vec<T>
+    {
+        v.sort();
+        v
+    }
+
+    fn sorted_models(models : Vec<HashMap<char, bool>>) -> Vec<Vec<(char, bool)>>
+    {
+        sorted(models.into_iter().map(|m| sorted(m.into_iter().collect())).collect())
+    }
+
+    // Every model of `f` over its atoms, from its truth table.
+    fn truth_table_models(f : &Formula) -> Vec<HashMap<char, bool>>
+    {
+        let atoms = atoms(f);
+        (0..(1u64 << atoms.len())).map(|row| assignment(&atoms, row)).filter(|m| eval(f, m)).collect()
+    }
+
+    #[test]
+    fn truth_table_streams_rows()
+    {
+        let mut out = Vec::new();
+        write_truth_table(&Formula::And(vec!(Formula::Atom('P'), Formula::Atom('Q'))), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines : Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "P Q | (P AND Q)");
+        assert_eq!(lines[4], "1 1 | 1");
+    }
+
+    #[test]
+    fn truth_table_csv_header_and_rows()
+    {
+        let csv = truth_table_csv(&Formula::Implies { l: box Formula::Atom('P'), r: box Formula::Atom('Q') });
+        let lines : Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "P,Q,result");
+        assert_eq!(lines[1], "0,0,1");
+        assert_eq!(lines[3], "1,0,0");
+    }
+
+    #[test]
+    fn parser_skips_comments()
+    {
+        let commented = p("P // first\n AND /* the second,\n over two lines */ (Q OR R)");
+        assert_eq!(commented, p("P AND (Q OR R)"));
+    }
+
+    #[test]
+    fn clauses_borrows_each_clause()
+    {
+        let f = p("(P OR ~Q) AND R AND (Q OR R OR ~P)");
+        let got : Vec<&[Formula]> = clauses(&f).unwrap().collect();
+        assert_eq!(got.len(), 3);
+        assert_eq!(got[0], &[p("P"), p("~Q")][..]);
+        assert_eq!(got[1], &[p("R")][..]);
+        assert_eq!(got[2], &[p("Q"), p("R"), p("~P")][..]);
+        assert!(clauses(&p("P AND (Q -> R)")).is_none());
+    }
+
+    #[test]
+    fn contradictory_units_are_trivially_unsat()
+    {
+        assert!(trivially_unsat(&to_clauses(&p("P AND ~P"))));
+        assert!(!trivially_unsat(&to_clauses(&p("P AND (~P OR Q)"))));
+    }
+
+    #[test]
+    fn print_styles_round_trip()
+    {
+        let f = p("~P AND (Q OR R) -> P <-> Q");
+        let ascii = print_with(&f, &PrintStyle::ascii());
+        let unicode = print_with(&f, &PrintStyle::unicode());
+        assert!(ascii != unicode);
+        assert_eq!(p(&ascii), f);
+        assert_eq!(p(&unicode), f);
+        assert_eq!(p(&print_with(&f, &PrintStyle::c_style())), f);
+    }
+
+    #[test]
+    fn polarities_of_atoms()
+    {
+        let polarities = atom_polarities(&p("(P OR ~Q) AND (Q OR R)"));
+        assert_eq!(polarities[&'P'], Polarity::Positive);
+        assert_eq!(polarities[&'R'], Polarity::Positive);
+        assert_eq!(polarities[&'Q'], Polarity::Both);
+    }
+
+    #[test]
+    fn unateness()
+    {
+        let monotone = p("P AND (Q OR R)");
+        assert!(is_positive_unate(&monotone, 'P'));
+        assert!(!is_negative_unate(&monotone, 'P'));
+        let xor = p("(P OR Q) AND ~(P AND Q)");
+        assert!(!is_positive_unate(&xor, 'P'));
+        assert!(!is_negative_unate(&xor, 'P'));
+    }
+
+    #[test]
+    fn redundant_variable_is_reported()
+    {
+        assert_eq!(redundant_vars(&p("((P OR Q) AND (P OR ~Q)) <-> P")), vec!('P', 'Q'));
+        assert_eq!(redundant_vars(&p("(P OR Q) AND (P OR ~Q)")), vec!('Q'));
+    }
+
+    #[test]
+    fn let_expands_to_the_inlined_formula()
+    {
+        let bound = p("let x := (A AND B) in x OR ~x");
+        let inlined = p("(A AND B) OR ~(A AND B)");
+        assert_eq!(bound, inlined);
+        for row in 0..4 {
+            let e = assignment(&['A', 'B'], row);
+            assert_eq!(eval(&bound, &e), eval(&inlined, &e));
+        }
+    }
+
+    #[test]
+    fn negate_in_place_matches_negate()
+    {
+        for s in &["P", "~(P AND Q)", "P -> (Q OR ~R)", "(P <-> Q) AND IFF(P, R, TRUE)", "~~(P OR FALSE)"] {
+            let mut f = p(s);
+            negate_in_place(&mut f);
+            assert_eq!(f, negate(p(s)));
+        }
+    }
+
+    #[test]
+    fn iff_all_agrees_on_all_or_nothing()
+    {
+        let f = p("IFF(A, B, C)");
+        for row in 0..8 {
+            assert_eq!(eval(&f, &assignment(&['A', 'B', 'C'], row)), row == 0 || row == 7);
+        }
+        assert_eq!(f.to_string(), "IFF(A, B, C)");
+    }
+
+    #[test]
+    fn compact_keeps_answers()
+    {
+        let mut solver = Solver::from_clauses(&cnf(&["A B", "A", "~A C", "A B C", "B ~C D"]));
+        assert!(solver.solve() != SatResult::Unsat);
+        let before = solver.num_clauses();
+        solver.compact(None);
+        assert!(solver.num_clauses() < before);
+        solver.add_clause(&[lit("~B")]);
+        match solver.solve() {
+            SatResult::Sat(m) => assert!(m[&'A'] && m[&'C'] && m[&'D'] && !m[&'B']),
+            SatResult::Unsat => panic!("still satisfiable"),
+        }
+        solver.add_clause(&[lit("~C")]);
+        assert_eq!(solver.solve(), SatResult::Unsat);
+    }
+
+    #[test]
+    fn compact_keeps_clauses_subsumed_by_learned_ones()
+    {
+        let mut solver = Solver::from_clauses(&cnf(&["A B C D", "A B C ~D"]));
+        assert!(solver.solve() != SatResult::Unsat);
+        solver.compact(None);
+        solver.compact(Some(2));
+        assert_eq!(solver.solve_with(&[lit("~A"), lit("~B"), lit("~C")]), SatResult::Unsat);
+    }
+
+    #[test]
+    fn failed_assumptions_form_a_core()
+    {
+        let mut solver = Solver::from_clauses(&cnf(&["~A ~B C", "~C D", "~E F"]));
+        assert_eq!(solver.solve_with(&[lit("A"), lit("E"), lit("B"), lit("~D")]), SatResult::Unsat);
+        let failed : BTreeSet<Literal> = solver.failed.iter().cloned().collect();
+        assert_eq!(failed, vec!(lit("A"), lit("B"), lit("~D")).into_iter().collect());
+        assert!(solver.solve_with(&[lit("A"), lit("E")]) != SatResult::Unsat);
+        assert!(solver.failed.is_empty());
+        solver.add_clause(&[lit("~F")]);
+        assert_eq!(solver.solve_with(&[lit("A"), lit("E")]), SatResult::Unsat);
+        assert_eq!(solver.failed, vec!(lit("E")));
+    }
+
+    #[test]
+    fn max_sat_finds_the_optimum()
+    {
+        let hard = cnf(&["A B", "~A ~B"]);
+        let soft = vec!((cnf(&["A"]).remove(0), 3), (cnf(&["B"]).remove(0), 2), (cnf(&["~A C"]).remove(0), 2), (cnf(&["~C"]).remove(0), 4));
+        // By enumeration: A=1 gives 3 plus the better of C (2) and ~C (4); B=1 gives 2 + 2 + 4.
+        let (model, weight) = max_sat(&hard, &soft).unwrap();
+        assert_eq!(weight, 8);
+        let won : u64 = soft.iter().filter(|s| satisfies(&[s.0.clone()], &model)).map(|s| s.1).sum();
+        assert_eq!(won, weight);
+        assert!(satisfies(&hard, &model));
+        assert!(max_sat(&cnf(&["A", "~A"]), &soft).is_none());
+    }
+
+    #[test]
+    fn normalize_is_idempotent_and_ac_canonical()
+    {
+        let a = p("(Q OR (P AND TRUE)) AND (R AND Q OR P)");
+        let b = p("(P OR R AND Q) AND (P OR Q)");
+        assert_eq!(normalize(a.clone()), normalize(b));
+        assert_eq!(normalize(normalize(a.clone())), normalize(a));
+    }
+
+    #[test]
+    fn normalize_collapses_complements()
+    {
+        assert_eq!(normalize(p("P OR ~P OR Q")), Formula::True);
+        assert_eq!(normalize(p("P AND ~P")), Formula::False);
+    }
+
+    #[test]
+    fn size_as_a_fold()
+    {
+        let f = p("(P -> ~Q) AND IFF(P, R) AND TRUE");
+        let folded = fold(&f, &mut |_, sizes : Vec<usize>| 1 + sizes.iter().sum::<usize>());
+        assert_eq!(folded, size(&f));
+        let negations = fold(&f, &mut |g, counts : Vec<usize>| counts.iter().sum::<usize>() + match g { &Formula::Not(_) => 1, _ => 0 });
+        assert_eq!(negations, 1);
+    }
+
+    #[test]
+    fn parser_reports_every_error()
+    {
+        let errors = parse_all_errors("(P AND ) OR (Q").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].span.start < errors[1].span.start);
+        assert_eq!(parse("(P AND ) OR (Q").unwrap_err(), errors[0]);
+    }
+
+    #[test]
+    fn parser_records_spans()
+    {
+        let spanned = parse_spanned("P AND Q").unwrap();
+        assert_eq!(spanned.formula, p("P AND Q"));
+        assert_eq!(spanned.spans.span, 0..7);
+        assert_eq!(spanned.spans.children[0].span, 0..1);
+        assert_eq!(spanned.spans.children[1].span, 6..7);
+    }
+
+    #[test]
+    fn prime_implicates_are_entailed_and_irredundant()
+    {
+        let f = p("(P OR Q) AND (~P OR R) AND (Q -> S)");
+        let implicates = prime_implicates(&f);
+        assert!(implicates.contains(&cnf(&["Q R"]).remove(0)));
+        let atoms = atoms(&f);
+        let models : Vec<HashMap<char, bool>> = (0..1u64 << atoms.len()).map(|row| assignment(&atoms, row)).filter(|e| eval(&f, e)).collect();
+        for (i, c) in implicates.iter().enumerate() {
+            assert!(models.iter().all(|m| satisfies(&[c.clone()], m)));
+            for (j, d) in implicates.iter().enumerate() {
+                assert!(i == j || !subsumes(c, d));
+            }
+        }
+    }
+
+    #[test]
+    fn minimal_cnf_is_equivalent_and_no_larger()
+    {
+        for s in &["(P AND Q) OR (~P AND R) OR (Q AND R)", "(P OR Q) AND (P OR ~Q) AND (R OR P)", "P <-> (Q AND ~R)"] {
+            let f = p(s);
+            let minimal = to_minimal_cnf(&f);
+            assert!(equivalent(&minimal, &f));
+            assert!(clauses(&minimal).is_some());
+            let naive = to_clauses(&f);
+            assert!(clauses(&minimal).unwrap().map(|c| c.len()).sum::<usize>() <= naive.iter().map(|c| c.len()).sum::<usize>());
+        }
+    }
+
+    #[test]
+    fn assert_and_check_flips_on_the_last_clause()
+    {
+        let mut solver = Solver::new();
+        for c in &["A B", "~A C", "~B C", "~C D"] {
+            assert!(solver.assert_and_check(cnf(&[c]).remove(0)) != SatResult::Unsat);
+        }
+        assert_eq!(solver.assert_and_check(cnf(&["~D"]).remove(0)), SatResult::Unsat);
+    }
+
+    #[test]
+    fn from_fn_builds_xor()
+    {
+        let f = from_fn(&['A', 'B'], |m| m[&'A'] ^ m[&'B']);
+        assert!(equivalent(&f, &p("(A OR B) AND ~(A AND B)")));
+    }
+
+    #[test]
+    fn falsified_clauses_under_a_partial_assignment()
+    {
+        let clauses = cnf(&["A B", "~A C", "~B D"]);
+        assert_eq!(falsified_clauses(&clauses, &env(&[('A', true), ('C', false)])), vec!(1));
+        assert!(falsified_clauses(&clauses, &env(&[('A', true)])).is_empty());
+    }
+
+    #[test]
+    fn smallest_normal_form_prefers_dnf()
+    {
+        let f = p("(A AND B AND C) OR (D AND E AND F) OR (G AND H)");
+        match to_smallest_normal_form(&f) {
+            Some(NormalForm::Dnf(g)) => {
+                assert!(equivalent(&g, &f));
+                assert_eq!(size(&g), size(&f));
+            }
+            other => panic!("expected a DNF, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn smallest_normal_form_compares_tidied_sizes()
+    {
+        // Five unit clauses A against the single term A, until its repeats are merged.
+        let f = and_of((0..5).map(|_| p("A")).collect());
+        assert_eq!(to_smallest_normal_form(&f), Some(NormalForm::Dnf(p("A"))));
+        let parity = (0..13u8).fold(p("A"), |acc, i| Formula::Iff { l: box acc, r: box Formula::Atom((b'B' + i) as char) });
+        assert_eq!(to_smallest_normal_form(&parity), None);
+    }
+
+    #[test]
+    fn trace_of_double_negation()
+    {
+        let (g, steps) = simplify_with_trace(p("~~A"));
+        assert_eq!(g, simplify(p("~~A")));
+        assert!(steps.iter().any(|s| s.law == Law::DoubleNegation && s.before == p("~~A") && s.after == p("A")));
+        let f = p("(P -> Q) AND ~(R OR ~P)");
+        let (g, steps) = simplify_with_trace(f.clone());
+        assert_eq!(replay(&f, &steps), g);
+        assert_eq!(g, simplify(f));
+    }
+
+    #[test]
+    fn trace_distributes_only_over_ors()
+    {
+        let f = p("(A AND B) OR (C AND (D OR E))");
+        let (g, steps) = simplify_with_trace(f.clone());
+        assert_eq!(replay(&f, &steps), g);
+        let laws : Vec<(Law, Formula)> = steps.iter().filter(|s| s.law == Law::Distribution || s.law == Law::OneTerm).map(|s| (s.law, s.before.clone())).collect();
+        assert_eq!(laws, vec!((Law::OneTerm, p("A AND B")), (Law::Distribution, p("C AND (D OR E)"))));
+        assert_eq!(simplify_with_stats(f).1.distributions, 1);
+    }
+
+    #[test]
+    fn tseitin_is_equisatisfiable()
+    {
+        let f = p("(P <-> Q) AND (Q OR R) AND ~(P AND R)");
+        assert!(equisatisfiable(&to_clauses(&f), &cnf(&["X", "~X Y"])));
+        assert!(!equisatisfiable(&cnf(&["A B"]), &cnf(&["A", "~A"])));
+        assert!(equisatisfiable(&cnf(&["A", "~A"]), &to_clauses(&p("(P OR Q) AND ~P AND ~Q"))));
+    }
+
+    #[test]
+    fn vocabulary_interns_names()
+    {
+        let mut vocabulary = Vocabulary::new();
+        let x = vocabulary.intern("x1");
+        let y = vocabulary.intern("speed");
+        assert_eq!(vocabulary.intern("x1"), x);
+        assert!(x != y);
+        assert_eq!(vocabulary.name(x), "x1");
+        assert_eq!(vocabulary.name(y), "speed");
+        assert_eq!(vocabulary.get("speed"), Some(y));
+        assert_eq!(vocabulary.len(), 2);
+    }
+
+    #[test]
+    fn fingerprint_ignores_order()
+    {
+        let a = cnf(&["A ~B C", "~A D", "B"]);
+        let b = cnf(&["B", "D ~A", "C A ~B"]);
+        assert_eq!(clause_set_fingerprint(&a), clause_set_fingerprint(&b));
+        assert!(clause_set_fingerprint(&a) != clause_set_fingerprint(&cnf(&["A ~B C", "~A D", "~B"])));
+        assert!(clause_set_fingerprint(&a) != clause_set_fingerprint(&cnf(&["A ~B C", "~A D"])));
+    }
+
+    #[test]
+    fn aig_of_or_is_one_inverted_and()
+    {
+        let aig = to_aig(&p("A OR B"));
+        assert_eq!(aig.node_count(), 1);
+        assert!(aig.root().is_inverted());
+        assert_eq!(aig.formula(aig.root()), p("~(~A AND ~B)"));
+        // Structural hashing shares the repeated subformula.
+        assert_eq!(to_aig(&p("(A AND B) OR ~(A AND B) OR C")).node_count(), 1);
+    }
+
+    #[test]
+    fn miter_agrees_with_truth_tables()
+    {
+        let mut rng = Rng(0x2545F4914F6CDD1D);
+        for _ in 0..200 {
+            let (a, b) = (rng.formula(4, 3), rng.formula(4, 3));
+            assert_eq!(equivalent_by_miter(&a, &b), equivalent(&a, &b), "{} vs {}", a, b);
+            assert!(equivalent_by_miter(&a, &simplify(a.clone())));
+        }
+        // Thirty atoms: the two sides differ only in the order of a long chain.
+        let atoms : Vec<Formula> = (0..30u8).map(|i| Formula::Atom((b'A' + i) as char)).collect();
+        let left = atoms.iter().skip(1).fold(atoms[0].clone(), |acc, x| Formula::Iff { l: box acc, r: box x.clone() });
+        let right = atoms.iter().rev().skip(1).fold(atoms[29].clone(), |acc, x| Formula::Iff { l: box acc, r: box x.clone() });
+        assert!(equivalent_by_miter(&left, &right));
+        assert!(!equivalent_by_miter(&left, &Formula::Not(box right)));
+    }
+
+    #[test]
+    fn forget_projects_atoms_away()
+    {
+        assert_eq!(forget(&p("P AND Q"), &['Q']), p("P"));
+        assert_eq!(forget(&p("(P OR Q) AND ~(P AND Q)"), &['Q']), Formula::True);
+    }
+
+    #[test]
+    fn interpolant_properties()
+    {
+        let a = p("P AND (P -> Q)");
+        let b = p("(Q -> R) AND ~R");
+        let i = interpolant(&a, &b).unwrap();
+        assert!(entails(&a, &i));
+        assert!(brute_sat(&to_clauses(&Formula::And(vec!(i.clone(), b.clone())))) == false);
+        assert!(atoms(&i).iter().all(|c| shared_atoms(&a, &b).contains(c) && atoms(&a).contains(c) && atoms(&b).contains(c)));
+        assert_eq!(interpolant(&p("P AND Q"), &p("Q OR R")), None);
+    }
+
+    #[test]
+    fn restarts_keep_answers()
+    {
+        let strategies = [RestartStrategy::Never, RestartStrategy::Luby { unit: 1 }, RestartStrategy::Luby { unit: 8 }, RestartStrategy::Geometric { first: 2, factor: 1.5 }];
+        let mut rng = Rng(88172645463325252);
+        for _ in 0..60 {
+            let clauses = rng.clauses(8, 36, 3);
+            let expected = brute_sat(&clauses);
+            for &strategy in &strategies {
+                let mut solver = Solver::with_config(SolverConfig { restart_strategy: strategy, ..SolverConfig::default() });
+                for c in &clauses {
+                    solver.add_clause(c);
+                }
+                match solver.solve() {
+                    SatResult::Sat(m) => assert!(expected && satisfies(&clauses, &m)),
+                    SatResult::Unsat => assert!(!expected),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn luby_restart_intervals()
+    {
+        let luby = RestartStrategy::Luby { unit: 3 };
+        let intervals : Vec<u64> = (0..15).map(|n| luby.interval(n).unwrap()).collect();
+        assert_eq!(intervals, vec!(3, 3, 6, 3, 3, 6, 12, 3, 3, 6, 3, 3, 6, 12, 24));
+        assert_eq!(RestartStrategy::Geometric { first: 10, factor: 2.0 }.interval(3), Some(80));
+        assert_eq!(RestartStrategy::Never.interval(0), None);
+    }
+
+    #[test]
+    fn restart_intervals_are_never_zero()
+    {
+        for strategy in &[RestartStrategy::Luby { unit: 0 }, RestartStrategy::Geometric { first: 0, factor: 2.0 }, RestartStrategy::Geometric { first: 10, factor: 0.5 }] {
+            assert!((0..100).all(|n| strategy.interval(n).unwrap() >= 1), "{:?}", strategy);
+            let mut solver = Solver::with_config(SolverConfig { restart_strategy: *strategy, ..SolverConfig::default() });
+            for c in &cnf(&["A B", "~A B", "A ~B", "~A ~B"]) {
+                solver.add_clause(c);
+            }
+            assert_eq!(solver.solve(), SatResult::Unsat);
+        }
+    }
+
+    #[test]
+    fn vsids_needs_fewer_decisions()
+    {
+        let mut rng = Rng(1181783497276652981);
+        let (mut vsids, mut fixed) = (0, 0);
+        for _ in 0..20 {
+            let clauses = rng.clauses(20, 85, 3);
+            let mut answers = Vec::new();
+            for &branching in &[Branching::Vsids { decay: 0.95 }, Branching::Static] {
+                let mut solver = Solver::with_config(SolverConfig { branching: branching, ..SolverConfig::default() });
+                for c in &clauses {
+                    solver.add_clause(c);
+                }
+                match solver.solve() {
+                    SatResult::Sat(m) => { assert!(satisfies(&clauses, &m)); answers.push(true) }
+                    SatResult::Unsat => answers.push(false),
+                }
+                if branching == Branching::Static { fixed += solver.decisions } else { vsids += solver.decisions }
+            }
+            assert_eq!(answers[0], answers[1]);
+        }
+        assert!(vsids < fixed, "{} decisions with VSIDS, {} without", vsids, fixed);
+    }
+
+    #[test]
+    fn phase_saving_keeps_answers_and_default_phase()
+    {
+        let mut rng = Rng(0x9E3779B97F4A7C15);
+        for _ in 0..40 {
+            let clauses = rng.clauses(8, 34, 3);
+            let expected = brute_sat(&clauses);
+            for &saving in &[false, true] {
+                let mut solver = Solver::with_config(SolverConfig { phase_saving: saving, ..SolverConfig::default() });
+                for c in &clauses {
+                    solver.add_clause(c);
+                }
+                assert_eq!(solver.solve() != SatResult::Unsat, expected);
+            }
+        }
+        // With nothing to go on, every decision is false, so only the last atom of the clause is set true.
+        let mut solver = Solver::from_clauses(&cnf(&["A B C D"]));
+        match solver.solve() {
+            SatResult::Sat(m) => assert_eq!(m.values().filter(|&&v| !v).count(), 3),
+            SatResult::Unsat => panic!("satisfiable"),
+        }
+    }
+
+    #[test]
+    fn prime_implicants_match_brute_force()
+    {
+        for s in &["(A AND B) OR (~A AND C)", "A <-> B", "(A OR B) AND (B OR C) AND (~A OR ~C)", "TRUE", "A AND ~A"] {
+            let f = p(s);
+            let enumerated = sorted(enumerate_prime_implicants_sat(&f).into_iter().map(sorted).collect());
+            assert_eq!(enumerated, brute_prime_implicants(&f), "{}", s);
+        }
+    }
+
+    #[test]
+    fn all_sat_matches_the_truth_table()
+    {
+        for s in &["(A OR B) AND (C -> ~A)", "A <-> (B OR C)", "A AND ~A", "A OR ~A"] {
+            let f = p(s);
+            assert_eq!(sorted_models(all_sat(&f)), sorted_models(truth_table_models(&f)), "{}", s);
+        }
+    }
+
+    #[test]
+    fn projected_count_ignores_auxiliary_atoms()
+    {
+        let f = p("A OR X");
+        assert_eq!(count_models(&f), 3);
+        assert_eq!(count_models_projected(&f, &['A']), 2);
+        let g = p("(X <-> (A AND B)) AND (Y <-> (A OR B)) AND (X OR Y)");
+        assert_eq!(count_models_projected(&g, &['A', 'B']), 3);
+    }
+
+    #[test]
+    fn nnf_size_estimate_is_exact()
+    {
+        let mut rng = Rng(7);
+        for _ in 0..100 {
+            let f = rng.formula(4, 4);
+            assert_eq!(nnf_size_estimate(&f), size(&to_nnf(f.clone())), "{}", f);
+        }
+    }
+
+    #[test]
+    fn kleene_unknowns()
+    {
+        let unknown : HashMap<char, Option<bool>> = vec!(('P', None), ('Q', Some(false))).into_iter().collect();
+        assert_eq!(eval_kleene(&p("P OR TRUE"), &unknown), Some(true));
+        assert_eq!(eval_kleene(&p("P AND TRUE"), &unknown), None);
+        assert_eq!(eval_kleene(&p("P AND Q"), &unknown), Some(false));
+        assert_eq!(eval_kleene(&p("Q -> P"), &unknown), Some(true));
+    }
+
+    #[test]
+    fn partial_eval_leaves_a_residual()
+    {
+        let f = p("(P AND Q) OR R");
+        assert!(equivalent(&partial_eval(&f, &env(&[('P', true)])), &p("Q OR R")));
+        assert_eq!(partial_eval(&f, &env(&[('P', false)])), p("R"));
+    }
+
+    #[test]
+    fn backbone_of_forced_atoms()
+    {
+        let b = backbone(&p("P AND (P OR Q)"));
+        assert_eq!(b.get(&'P'), Some(&true));
+        assert!(!b.contains_key(&'Q'));
+        assert!(backbone(&p("P AND ~P")).is_empty());
+    }
+
+    #[test]
+    fn dont_cares_are_outside_the_context()
+    {
+        // A one-hot pair never has both atoms set or both clear.
+        let cares = dont_cares(&p("A AND B"), &p("(A OR B) AND ~(A AND B)"));
+        assert_eq!(sorted_models(cares), vec!(vec!(('A', false), ('B', false)), vec!(('A', true), ('B', true))));
+        assert!(dont_cares(&p("A"), &p("TRUE")).is_empty());
+    }
+
+    #[test]
+    fn bv_eq_compares_bitwise()
+    {
+        let f = bv_eq(&['a', 'b'], &['x', 'y']);
+        for row in 0..16 {
+            let e = assignment(&['a', 'b', 'x', 'y'], row);
+            assert_eq!(eval(&f, &e), e[&'a'] == e[&'x'] && e[&'b'] == e[&'y']);
+        }
+        assert_eq!(bv_eq(&[], &[]), Formula::True);
+    }
+
+    #[test]
+    fn later_steps_expand_iff_all()
+    {
+        let f = Formula::IffAll(vec!(p("A"), p("B AND C"), p("~D")));
+        for g in vec!(f.clone(), Formula::Not(box f.clone())) {
+            assert!(equivalent(&simplify2(g.clone()), &g));
+            let (cnf, _) = simplify3_within(g.clone(), 1000).unwrap();
+            assert_eq!(cnf, simplify(g.clone()));
+            assert!(equivalent(&cnf, &g));
+        }
+        assert_eq!(simplify2(Formula::Not(box f.clone())), to_nnf(Formula::Not(box f)));
+    }
+
+    #[test]
+    fn later_steps_drop_annotations()
+    {
+        let note = |f : Formula| Formula::Annotated { note: "n".to_string(), inner: box f };
+        let f = Formula::And(vec!(note(p("A OR ~B")), Formula::Not(box note(p("C AND D")))));
+        assert_eq!(simplify2(f.clone()), to_nnf(f.clone()));
+        let (cnf, _) = simplify3_within(f.clone(), 1000).unwrap();
+        assert_eq!(cnf, simplify(f.clone()));
+        assert!(equivalent(&cnf, &f));
+    }
+
+    #[test]
+    fn try_simplify_stops_an_explosion()
+    {
+        let wide = and_of((0..12u8).map(|i| Formula::Or(vec!(Formula::Atom((b'A' + 2 * i) as char), Formula::Atom((b'B' + 2 * i) as char)))).collect());
+        assert_eq!(try_simplify(wide, 1000), Err(SimplifyError::Explosion));
+        let small = p("(A OR B) AND (C OR D)");
+        assert_eq!(try_simplify(small.clone(), 1000), Ok(simplify(small)));
+    }
+
+    #[test]
+    fn clause_count_estimate()
+    {
+        for s in &["(A AND B) OR (C AND D)", "(A OR B) -> (C AND ~D)", "A <-> (B OR C)", "IFF(A, B, C) OR D", "~((A OR B) AND C)"] {
+            let f = p(s);
+            assert_eq!(cnf_clause_count_estimate(&f), nnf_clauses(&to_nnf(f.clone())).len() as u128, "{}", s);
+        }
+        let pathological = or_of((0..130).map(|_| p("A AND B")).collect());
+        assert_eq!(cnf_clause_count_estimate(&pathological), u128::MAX);
+    }
+
+    #[test]
+    fn merge_unifies_or_separates_shared_atoms()
+    {
+        let (a, b) = (p("P AND Q"), p("Q -> R"));
+        let (unified, renaming) = merge(&a, &b, false);
+        assert!(renaming.is_empty());
+        assert_eq!(atoms(&unified), vec!('P', 'Q', 'R'));
+        assert_eq!(count_models(&unified), 1);
+        let (apart, renaming) = merge(&a, &b, true);
+        assert_eq!(renaming.len(), 1);
+        assert_eq!(atoms(&apart).len(), 4);
+        assert!(atoms(&apart).contains(&renaming[&'Q']));
+    }
+
+    #[test]
+    fn explanation_names_the_satisfying_disjunct()
+    {
+        let explanation = explain_model(&p("P OR Q"), &env(&[('P', true), ('Q', false)]));
+        assert_eq!(explanation, "(P OR Q) is true because P is true\n  P is true in the model\n");
+    }
+
+    #[test]
+    fn influence_of_each_atom()
+    {
+        let xor = influence(&p("(A OR B) AND ~(A AND B)"));
+        assert_eq!(xor[&'A'], 1.0);
+        assert_eq!(xor[&'B'], 1.0);
+        assert_eq!(influence(&p("A OR TRUE"))[&'A'], 0.0);
+        assert_eq!(influence(&p("A AND B"))[&'A'], 0.5);
+    }
+
+    #[test]
+    fn canonical_clauses_of_equivalent_cnfs()
+    {
+        let a = p("(A OR ~B OR A) AND (C OR ~C) AND (B OR C) AND (C OR B)");
+        let b = p("(C OR B) AND (~B OR A)");
+        assert_eq!(canonical_clauses(&a), canonical_clauses(&b));
+        assert_eq!(canonical_clauses(&b), cnf(&["A ~B", "B C"]));
+    }
+
+    #[test]
+    fn xor_cycle_is_unsatisfiable()
+    {
+        // A = B, B = C, C != A.
+        let f = p("(A <-> B) AND (B <-> C) AND ~(C <-> A)");
+        let xors = extract_xor_clauses(&f);
+        assert_eq!(xors.len(), 3);
+        assert_eq!(solve_xor(&xors), None);
+        let consistent = extract_xor_clauses(&p("(A <-> B) AND ~(B <-> C) AND C"));
+        let m = solve_xor(&consistent).unwrap();
+        assert!(m[&'C'] && !m[&'B'] && !m[&'A']);
+    }
+
+    #[test]
+    fn preserving_implications()
+    {
+        assert_eq!(simplify_preserve_implications(p("(A -> B) AND TRUE")), p("A -> B"));
+        assert_eq!(simplify_preserve_implications(p("(B AND (A OR FALSE)) <-> C")), p("(B AND A) <-> C"));
+    }
+
+    #[test]
+    fn tseitin_definition_of_and()
+    {
+        let defs = tseitin_definitions(&p("A AND B"));
+        assert_eq!(defs.len(), 1);
+        let (x, ref gate) = defs[0];
+        assert!(is_fresh(x));
+        assert_eq!(*gate, GateDef::And(vec!(lit("A"), lit("B"))));
+        let xs = |s : &str| s.replace('x', &x.to_string());
+        let expected : Vec<Vec<Literal>> = cnf(&[&xs("~x A"), &xs("~x B"), &xs("x ~A ~B")]);
+        assert_eq!(gate.clauses(x), expected);
+    }
+
+    #[test]
+    fn tseitin_of_a_wide_formula()
+    {
+        let atoms : Vec<Formula> = (0..120).map(|i| Formula::Atom(std::char::from_u32(0x100 + i).unwrap())).collect();
+        let ors : Vec<Formula> = (0..120).flat_map(|i| (i + 1..120).map(move |j| (i, j))).take(7000)
+            .map(|(i, j)| Formula::Or(vec!(atoms[i].clone(), atoms[j].clone()))).collect();
+        let clauses = to_cnf_tseitin(&Formula::And(ors));
+        let fresh : BTreeSet<char> = clauses.iter().flat_map(|c| c.iter().map(|l| l.atom)).filter(|&c| is_fresh(c)).collect();
+        assert!(fresh.len() > 0xF900 - 0xE000);
+        let mut used = fresh.clone();
+        assert!(!fresh.contains(&fresh_atom(&mut used)));
+    }
+
+    #[test]
+    fn equivalent_atoms_are_detected()
+    {
+        let found = detect_equivalences(&p("(A <-> B) AND (B OR C) AND (C -> D)"));
+        assert_eq!(found, vec!(('A', 'B', true)));
+        assert_eq!(detect_equivalences(&p("~(A <-> B) AND C")), vec!(('A', 'B', false)));
+    }
+
+    #[test]
+    fn equivalent_atoms_are_substituted()
+    {
+        let f = p("(A <-> B) AND (B OR C) AND (~B -> D)");
+        let g = substitute_equivalences(f.clone());
+        assert!(!atoms(&g).contains(&'B'));
+        assert_eq!(g, p("(A <-> A) AND (A OR C) AND (~A -> D)"));
+        assert!(equisatisfiable(&to_clauses(&f), &to_clauses(&g)));
+    }
+
+    #[test]
+    fn for_each_model_counts_and_breaks()
+    {
+        let f = p("A OR B OR C");
+        let mut calls = 0;
+        for_each_model(&f, None, |m| { assert!(eval(&f, m)); calls += 1; ControlFlow::Continue(()) });
+        assert_eq!(calls, 7);
+        calls = 0;
+        for_each_model(&f, Some(4), |_| { calls += 1; ControlFlow::Continue(()) });
+        assert_eq!(calls, 4);
+        calls = 0;
+        for_each_model(&f, None, |_| { calls += 1; if calls == 2 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) } });
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn minimal_support_keeps_one_disjunct()
+    {
+        let support = minimal_support(&p("P OR Q"), &env(&[('P', true), ('Q', true)]));
+        assert_eq!(support.len(), 1);
+        assert!(support.values().all(|&v| v));
+        let support = minimal_support(&p("P AND (Q OR R)"), &env(&[('P', true), ('Q', false), ('R', true)]));
+        assert_eq!(support, env(&[('P', true), ('R', true)]));
+    }
+
+    #[test]
+    fn stats_for_double_negation()
+    {
+        let (g, stats) = simplify_with_stats(p("~~A"));
+        assert_eq!(g, simplify(p("~~A")));
+        assert_eq!(stats.double_negations, 1);
+        assert_eq!(stats.distributions, 0);
+        let (_, stats) = simplify_with_stats(p("~(A OR B) AND (C OR D)"));
+        assert_eq!(stats.de_morgans, 1);
+        assert_eq!(stats.distributions, 1);
+    }
+
+    #[test]
+    fn annotations_are_transparent()
+    {
+        let bare = p("(A OR B) AND ~C");
+        let noted = Formula::And(vec!(
+            Formula::Annotated { note: "req 1".to_string(), inner: box p("A OR B") },
+            Formula::Annotated { note: "req 2".to_string(), inner: box p("~C") }));
+        for row in 0..8 {
+            let e = assignment(&['A', 'B', 'C'], row);
+            assert_eq!(eval(&noted, &e), eval(&bare, &e));
+        }
+        assert_eq!(strip_annotations(noted.clone()), bare);
+        assert_eq!(noted.to_string(), bare.to_string());
+        assert_eq!(format!("{:#}", noted), "((A OR B) [req 1] AND ~(C) [req 2])");
+        assert_eq!(simplify(noted), simplify(bare));
+    }
+
+    #[test]
+    fn unsat_core_by_label()
+    {
+        let named = |name : &str, c : &str| (name.to_string(), cnf(&[c]).remove(0));
+        let clauses = vec!(named("wheels", "A B"), named("budget", "~A"), named("colour", "C D"), named("brakes", "~B"), named("paint", "~C"));
+        assert_eq!(labeled_unsat_core(&clauses), vec!("wheels", "budget", "brakes"));
+        assert!(labeled_unsat_core(&clauses[2..]).is_empty());
+    }
+
+    #[test]
+    fn mss_and_mcs_match_brute_force()
+    {
+        let clauses = cnf(&["A", "~A", "A B", "~B", "B ~C", "C"]);
+        let n = clauses.len();
+        let subset = |mask : u32| -> Vec<usize> { (0..n).filter(|&i| mask & (1 << i) != 0).collect() };
+        let sat = |mask : u32| brute_sat(&subset(mask).iter().map(|&i| clauses[i].clone()).collect::<Vec<_>>());
+        let maximal : Vec<Vec<usize>> = (0..1u32 << n).filter(|&m| sat(m) && (0..n).all(|i| m & (1 << i) != 0 || !sat(m | (1 << i)))).map(subset).collect();
+        assert_eq!(sorted(maximal_satisfiable_subsets(&clauses)), sorted(maximal.clone()));
+        let corrections : Vec<Vec<usize>> = maximal.iter().map(|s| (0..n).filter(|i| !s.contains(i)).collect()).collect();
+        assert_eq!(sorted(minimal_correction_sets(&clauses)), sorted(corrections));
+        assert_eq!(maximal_satisfiable_subsets(&cnf(&["A", "B"])), vec!(vec!(0, 1)));
+    }
+
+    #[test]
+    fn truth_table_bits_match_eval()
+    {
+        for s in &["A -> B", "IFF(A, B, C, D) OR ~E", "(A AND B AND C AND D AND E AND F) OR (G <-> H)", "TRUE"] {
+            let f = p(s);
+            let atoms = atoms(&f);
+            let bits = truth_table_bits(&f);
+            assert_eq!(bits.len(), 1 << atoms.len());
+            for i in 0..bits.len() {
+                assert_eq!(bits.get(i), eval(&f, &assignment(&atoms, i as u64)), "{} row {}", s, i);
+            }
+        }
+    }
+
+    #[test]
+    fn equivalent_small_agrees_with_equivalent()
+    {
+        let mut rng = Rng(99);
+        for _ in 0..300 {
+            let (a, b) = (rng.formula(3, 3), rng.formula(3, 3));
+            assert_eq!(equivalent_small(&a, &b), equivalent(&a, &b), "{} vs {}", a, b);
+        }
+        assert!(equivalent_small(&p("A OR (B AND ~B)"), &p("A")));
+    }
+
+    #[test]
+    fn diff_models_of_or_and_atom()
+    {
+        assert_eq!(diff_models(&p("P OR Q"), &p("P")), vec!(env(&[('P', false), ('Q', true)])));
+        assert!(diff_models(&p("P -> Q"), &p("~P OR Q")).is_empty());
+    }
+
+    #[test]
+    fn compiled_eval_matches_eval()
+    {
+        let f = p("(A -> ~B) <-> IFF(C, A AND D, TRUE) OR ~(D OR FALSE)");
+        let compiled = compile(&f);
+        let atoms = atoms(&f);
+        for row in 0..16 {
+            let e = assignment(&atoms, row);
+            let values : Vec<bool> = atoms.iter().map(|c| e[c]).collect();
+            assert_eq!(compiled.eval(&values), eval(&f, &e));
+        }
+    }
+
+    #[test]
+    fn gray_code_visits_each_assignment_once()
+    {
+        let f = p("A AND B OR C");
+        let visited : Vec<HashMap<char, bool>> = models_gray(&f).collect();
+        assert_eq!(visited.len(), 8);
+        assert_eq!(sorted_models(visited.clone()).len(), 8);
+        assert!(sorted_models(visited.clone()).windows(2).all(|w| w[0] != w[1]));
+        for w in visited.windows(2) {
+            assert_eq!(w[0].iter().filter(|&(c, v)| w[1][c] != *v).count(), 1);
+        }
+    }
+
+    #[test]
+    fn self_duality()
+    {
+        assert!(is_self_dual(&p("(A AND B) OR (A AND C) OR (B AND C)")));
+        assert!(!is_self_dual(&p("A AND B")));
+        assert!(is_self_dual(&p("~A")));
+    }
+
+    #[test]
+    fn anf_of_or()
+    {
+        let anf = to_anf(&p("A OR B"));
+        assert_eq!(anf.terms, vec!(vec!('A'), vec!('B'), vec!('A', 'B')));
+        assert_eq!(anf.to_string(), "A XOR B XOR (A AND B)");
+        assert!(equivalent(&anf.to_formula(), &p("A OR B")));
+        assert_eq!(to_anf(&p("~A")).to_string(), "TRUE XOR A");
+    }
+
+    #[test]
+    fn affine_functions()
+    {
+        assert!(is_affine(&p("~(~(A <-> B) <-> C)")));
+        assert!(is_affine(&p("~A")));
+        assert!(!is_affine(&p("A AND B")));
+    }
+
+    #[test]
+    fn bounded_resolution()
+    {
+        assert_eq!(resolution_refutation_bounded(&p("(A OR B) AND (A OR ~B) AND (~A OR C) AND ~C"), 2), Some(true));
+        assert_eq!(resolution_refutation_bounded(&p("(A OR B) AND ~A"), 2), Some(false));
+        assert_eq!(resolution_refutation_bounded(&p("(A OR B OR C) AND (~A OR D OR E) AND ~B"), 2), None);
+    }
+
+    #[test]
+    fn implication_graph_shows_the_conflict()
+    {
+        let mut solver = Solver::from_clauses(&cnf(&["~A B", "~A C", "~B ~C"]));
+        assert!(!solver.decide(lit("A")));
+        let dot = solver.implication_graph_dot();
+        assert!(dot.starts_with("digraph implications {\n"));
+        assert!(dot.contains("conflict [label=\"conflict c2\", shape=octagon];"));
+        assert!(dot.contains("v0 [label=\"A @1\", shape=box];"));
+        assert!(dot.contains("v0 -> v1 [label=\"c0\"];"));
+        assert!(dot.contains("v1 -> conflict;"));
+        assert!(dot.contains("v2 -> conflict;"));
+    }
+
+    #[test]
+    fn cache_hits_on_ac_equal_inputs()
+    {
+        let mut cache = SimplifyCache::new();
+        let first = cache.simplify(p("(A -> B) AND (C OR D)"));
+        assert_eq!(first, simplify(p("(A -> B) AND (C OR D)")));
+        assert_eq!(cache.simplify(p("(A -> B) AND (C OR D)")), first);
+        assert_eq!(cache.simplify(p("(D OR C) AND (A -> B)")), first);
+        assert_eq!((cache.len(), cache.hits, cache.misses), (1, 2, 1));
+        cache.clear();
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn learned_clauses_stay_bounded()
+    {
+        let mut rng = Rng(0xDEADBEEF);
+        for _ in 0..10 {
+            let clauses = rng.clauses(40, 170, 3);
+            let mut bounded = Solver::with_config(SolverConfig { max_learned: Some(20), permanent_lbd: 2, ..SolverConfig::default() });
+            let mut unbounded = Solver::with_config(SolverConfig { max_learned: None, ..SolverConfig::default() });
+            for c in &clauses {
+                bounded.add_clause(c);
+                unbounded.add_clause(c);
+            }
+            let answer = bounded.solve();
+            if let SatResult::Sat(ref m) = answer {
+                assert!(satisfies(&clauses, m));
+            }
+            assert_eq!(answer == SatResult::Unsat, unbounded.solve() == SatResult::Unsat);
+            let permanent = bounded.clauses.iter().filter(|c| c.learned && c.lbd <= 2).count();
+            assert!(bounded.num_learned() <= 20 + permanent + 1, "{} learned clauses kept", bounded.num_learned());
+        }
+    }
+
+    #[test]
+    fn why_unsat_omits_irrelevant_conjuncts()
+    {
+        let core = why_unsat(&p("P AND ~P AND Q")).unwrap();
+        assert!(equivalent(&core, &p("P AND ~P")));
+        assert!(!atoms(&core).contains(&'Q'));
+        assert_eq!(why_unsat(&p("P AND Q")), None);
+    }
+
+    #[test]
+    fn negation_binds_tightest()
+    {
+        assert_eq!(p("~P AND Q"), Formula::And(vec!(p("~(P)"), p("Q"))));
+        assert_eq!(p("~P OR Q AND R"), Formula::Or(vec!(p("~(P)"), p("Q AND R"))));
+        assert_eq!(p("~~P"), Formula::Not(box Formula::Not(box Formula::Atom('P'))));
+        assert_eq!(p("~(P AND Q)"), Formula::Not(box p("P AND Q")));
+    }
+
+    #[test]
+    fn units_propagate_through_the_formula()
+    {
+        assert_eq!(propagate_units(p("P AND (P -> Q) AND (Q -> R)")), p("P AND Q AND R"));
+        assert_eq!(propagate_units(p("P AND (~P OR Q) AND ~Q")), Formula::False);
+    }
+
+    #[test]
+    fn depth_limit()
+    {
+        let deep = (0..50).fold(p("A"), |acc, _| Formula::Not(box acc));
+        assert_eq!(simplify_depth_limited(&deep, 20), Err(DepthExceeded { max_depth: 20 }));
+        assert_eq!(simplify_depth_limited(&deep, 51), Ok(p("A")));
+        assert_eq!(simplify_depth_limited(&p("A AND (B OR C)"), 3), Ok(simplify(p("A AND (B OR C)"))));
+    }
+
+    #[test]
+    fn depth_limit_refuses_a_very_deep_formula()
+    {
+        let deep = (0..2_000_000).fold(p("A"), |acc, _| Formula::Not(box acc));
+        assert_eq!(simplify_depth_limited(&deep, 100), Err(DepthExceeded { max_depth: 100 }));
+        // Dropping it would overflow the stack just as simplifying it would.
+        mem::forget(deep);
+    }
+
+    #[test]
+    fn implication_normal_form()
+    {
+        assert_eq!(to_implication_normal_form(p("~A OR ~B OR C")), p("A AND B -> C"));
+        assert_eq!(to_implication_normal_form(p("~A OR B OR C")), p("A -> B OR C"));
+        assert_eq!(to_implication_normal_form(p("(A OR B) AND (~A OR ~B)")), p("(A OR B) AND (A AND B -> FALSE)"));
+    }
+
+    #[test]
+    fn shrink_finds_a_minimal_witness()
+    {
+        // x XOR y for distinct atoms x and y, written (x OR y) AND ~(x AND y).
+        let is_xor = |g : &Formula| match g {
+            &Formula::And(ref v) if v.len() == 2 => match (&v[0], &v[1]) {
+                (&Formula::Or(ref a), &Formula::Not(box Formula::And(ref b))) => a == b && a.len() == 2 && a[0] != a[1] && a.iter().all(|x| match x { &Formula::Atom(_) => true, _ => false }),
+                _ => false,
+            },
+            _ => false,
+        };
+        let contains_xor = |g : &Formula| fold(g, &mut |h, found : Vec<bool>| is_xor(h) || found.contains(&true));
+        let big = p("(C -> D) AND ((E OR ~F) <-> ((A OR B) AND ~(A AND B) AND G)) AND (H OR C)");
+        let small = shrink(&big, &contains_xor);
+        assert!(contains_xor(&small));
+        assert_eq!(small, p("(A OR B) AND ~(A AND B)"));
+    }
+
+    #[test]
+    fn shrink_stops_at_the_first_step_that_fails()
+    {
+        let wide = and_of((0..3000).map(|i| Formula::Atom(std::char::from_u32(0x100 + i).unwrap())).collect());
+        let calls = std::cell::Cell::new(0);
+        let small = shrink(&wide, |g| { calls.set(calls.get() + 1); atoms(g).contains(&'\u{100}') });
+        assert_eq!(small, Formula::Atom('\u{100}'));
+        // TRUE, FALSE and then the first atom, after which no step is smaller.
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn trivial_subformulas_are_pruned()
+    {
+        let f = p("(X OR ~X) AND Y");
+        assert_eq!(prune_trivial_subformulas(f.clone()), p("Y"));
+        assert!(equivalent(&prune_trivial_subformulas(f.clone()), &f));
+        let g = p("(X AND ~X) OR (Y -> (Z AND ~Z))");
+        assert_eq!(prune_trivial_subformulas(g.clone()), p("~Y"));
+        assert!(equivalent(&prune_trivial_subformulas(g.clone()), &g));
+    }
+
+    #[test]
+    fn dimacs_with_comments_and_split_clauses()
+    {
+        let input = "c a small instance\np cnf 3 3\n1 -2 0\nc between clauses\n2 3\n -1 0 -3 0\n";
+        let (clauses, vocabulary) = parse_dimacs(input.as_bytes()).unwrap();
+        let atom = |n : &str| vocabulary_atom(vocabulary.get(n).unwrap());
+        let (x1, x2, x3) = (atom("1"), atom("2"), atom("3"));
+        assert_eq!(clauses, vec!(
+            vec!(Literal { atom: x1, negated: false }, Literal { atom: x2, negated: true }),
+            vec!(Literal { atom: x2, negated: false }, Literal { atom: x3, negated: false }, Literal { atom: x1, negated: true }),
+            vec!(Literal { atom: x3, negated: true })));
+        assert!(parse_dimacs("p cnf 2 1\n1 2\n".as_bytes()).is_err());
+        assert!(parse_dimacs("p cnf 2 2\n1 2 0\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn dimacs_interns_only_mentioned_variables()
+    {
+        let (clauses, vocabulary) = parse_dimacs("p cnf 4000000000 1\n1 -3999999999 0\n".as_bytes()).unwrap();
+        assert_eq!(vocabulary.len(), 2);
+        assert_eq!(vocabulary.get("3999999999"), Some(AtomId(1)));
+        assert_eq!(clauses[0][1], Literal { atom: vocabulary_atom(AtomId(1)), negated: true });
+
+        let count = 0xE0000;
+        let mut input = format!("p cnf {} {}\n", count + 1, count + 1);
+        for n in 1..=count + 1 {
+            input.push_str(&format!("{} 0\n", n));
+        }
+        let error = parse_dimacs(input.as_bytes()).unwrap_err();
+        let last = input.trim_end().rfind('\n').unwrap() + 1;
+        assert_eq!(error.span, last..last + (count + 1).to_string().len());
+    }
+
+    #[test]
+    fn resolution_derives_units()
+    {
+        let derived = derive_units(cnf(&["A B", "A ~B", "~A C D", "A E", "B C"]));
+        assert_eq!(derived, cnf(&["A", "B C", "C D"]));
+        assert_eq!(derive_units(cnf(&["A B", "A ~B", "~A"])), vec!(vec!()));
+    }
+
+    #[test]
+    fn neighbors_within_one_flip()
+    {
+        let neighbors = solution_neighbors(&p("P OR Q"), &env(&[('P', true), ('Q', false)]), 1);
+        assert!(neighbors.contains(&env(&[('P', true), ('Q', true)])));
+        assert!(!neighbors.contains(&env(&[('P', false), ('Q', false)])));
+        assert_eq!(neighbors.len(), 1);
+        assert_eq!(solution_neighbors(&p("P OR Q"), &env(&[('P', true), ('Q', false)]), 2).len(), 2);
+    }
+
+    #[test]
+    fn essential_implicant_of_a_kmap()
+    {
+        // A ~B is the only prime covering A ~B ~C; B C is the only one covering ~A B C; A C is redundant.
+        let f = p("(A AND ~B) OR (B AND C) OR (A AND C)");
+        assert_eq!(essential_prime_implicants(&f), vec!(vec!(lit("A"), lit("~B")), vec!(lit("B"), lit("C"))));
+        assert_eq!(enumerate_prime_implicants_sat(&f).len(), 3);
+    }
+
+    #[test]
+    fn dont_cares_shrink_the_cover()
+    {
+        let atoms = ['A', 'B', 'C'];
+        let on = [1, 3, 5];
+        let without = minimize_incomplete(&on, &[], &atoms);
+        let with = minimize_incomplete(&on, &[7], &atoms);
+        assert!(size(&with) < size(&without), "{} is not smaller than {}", with, without);
+        assert_eq!(with, p("C"));
+        for &row in &on {
+            assert!(eval(&with, &assignment(&atoms, row as u64)) && eval(&without, &assignment(&atoms, row as u64)));
+        }
+        assert!(!eval(&without, &assignment(&atoms, 7)));
+    }
+
+    #[test]
+    fn egraph_is_no_worse_than_simplify()
+    {
+        let battery = ["(A AND B) OR (A AND C)", "~~A AND (A OR B)", "(A -> B) AND (B -> A)", "((A OR B) AND ~A) OR (A AND ~B)",
+            "IFF(A, B) OR ~(A <-> B)", "(A AND TRUE) OR (FALSE AND B)", "~(~A OR ~B) AND (C OR ~C)"];
+        for s in &battery {
+            let f = p(s);
+            let g = simplify_egraph(&f);
+            assert!(size(&g) <= size(&simplify(f.clone())), "{} gave {}", s, g);
+            assert!(equivalent(&g, &f), "{} gave {}", s, g);
+        }
+        assert_eq!(size(&simplify_egraph(&p("(A AND B) OR (A AND C)"))), 5);
+    }
+
+    #[test]
+    fn check_model_reports_the_failing_clause()
+    {
+        let f = p("(A OR B) AND (~A OR C) AND (B -> C)");
+        assert_eq!(check_model(&f, &env(&[('A', true), ('B', false), ('C', true)])), Ok(()));
+        assert_eq!(check_model(&f, &env(&[('A', true), ('B', false), ('C', false)])), Err(ModelError::Falsified(p("~A OR C"))));
+        assert_eq!(check_model(&f, &env(&[('A', true), ('B', false)])), Err(ModelError::Incomplete('C')));
+    }
+
+    #[test]
+    fn soft_constraints_change_the_optimum()
+    {
+        let mut solver = MaxSatSolver::new();
+        solver.add_hard(&[lit("A"), lit("B")]);
+        solver.add_soft(&[lit("~A")], 2);
+        let (m, w) = solver.optimize(Direction::Maximize).unwrap();
+        assert_eq!(w, 2);
+        assert!(!m[&'A'] && m[&'B']);
+        solver.add_soft(&[lit("~B")], 3);
+        let (m, w) = solver.optimize(Direction::Maximize).unwrap();
+        assert_eq!(w, 3);
+        assert!(m[&'A'] && !m[&'B']);
+        assert_eq!(solver.optimize(Direction::Minimize).unwrap().1, 0);
+        solver.add_hard(&[lit("~A")]);
+        assert_eq!(solver.optimize(Direction::Maximize).unwrap().1, 2);
+        assert!(solver.optimize(Direction::Maximize).unwrap().0.keys().all(|&c| c == 'A' || c == 'B'));
+    }
+
+    #[test]
+    fn core_guided_search_matches_enumeration()
+    {
+        let mut rng = Rng(0x5DEECE66D);
+        for _ in 0..30
+        {
+            let mut solver = MaxSatSolver::new();
+            let mut hard = Vec::new();
+            let mut soft = Vec::new();
+            for _ in 0..3
+            {
+                for c in rng.clauses(5, 2, 2) {
+                    solver.add_hard(&c);
+                    hard.push(c);
+                }
+                for c in rng.clauses(5, 4, 2) {
+                    let weight = 1 + rng.below(5);
+                    solver.add_soft(&c, weight);
+                    soft.push((c, weight));
+                }
+                let atoms : Vec<char> = (b'A'..b'F').map(|b| b as char).collect();
+                let weights : Vec<u64> = (0..32).map(|row| assignment(&atoms, row)).filter(|e| satisfies(&hard, e))
+                    .map(|e| soft.iter().filter(|s| satisfies(&[s.0.clone()], &e)).map(|s| s.1).sum()).collect();
+                for &(direction, best) in &[(Direction::Maximize, weights.iter().max()), (Direction::Minimize, weights.iter().min())]
+                {
+                    match (solver.optimize(direction), best)
+                    {
+                        (Some((model, weight)), Some(&best)) => {
+                            assert_eq!(weight, best);
+                            assert!(satisfies(&hard, &model));
+                            assert_eq!(soft.iter().filter(|s| satisfies(&[s.0.clone()], &model)).map(|s| s.1).sum::<u64>(), weight);
+                        }
+                        (None, None) => {}
+                        (answer, best) => panic!("{:?} but the optimum is {:?}", answer.map(|a| a.1), best),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn relaxation_atoms_never_clash()
+    {
+        let mut solver = MaxSatSolver::new();
+        solver.add_soft(&[lit("A")], 1);
+        for c in &to_cnf_tseitin(&p("~(B AND C) AND ~A")) {
+            solver.add_hard(c);
+        }
+        let (m, w) = solver.optimize(Direction::Maximize).unwrap();
+        assert_eq!(w, 0);
+        assert!(!m[&'A']);
+
+        let private = std::char::from_u32(0xE000).unwrap();
+        let mut solver = MaxSatSolver::new();
+        solver.add_hard(&[Literal { atom: private, negated: false }]);
+        solver.add_soft(&[Literal { atom: private, negated: true }, lit("A")], 1);
+        let (m, w) = solver.optimize(Direction::Maximize).unwrap();
+        assert_eq!(w, 1);
+        assert_eq!(m.len(), 2);
+        assert!(m[&private] && m[&'A']);
+    }
+
+    #[test]
+    fn netlist_simulates_the_formula()
+    {
+        for s in &["(A -> B) AND ~(C <-> A)", "A OR ~A", "A AND ~A"] {
+            let f = p(s);
+            let netlist = to_netlist(&f);
+            let atoms = atoms(&f);
+            for row in 0..(1u64 << atoms.len()) {
+                let e = assignment(&atoms, row);
+                assert_eq!(netlist.eval(&e), eval(&f, &e), "{}", s);
+            }
+        }
+        let verilog = to_netlist(&p("A AND ~B")).to_verilog();
+        assert!(verilog.starts_with("module formula(input A, input B, output out);\n"));
+        assert!(verilog.ends_with("endmodule\n"));
+    }
+
+    #[test]
+    fn dag_size_counts_shared_subformulas_once()
+    {
+        let f = p("(A AND B) OR (A AND B)");
+        assert_eq!(size(&f), 7);
+        assert_eq!(dag_size(&f), 4);
+    }
+
+    #[test]
+    fn resolution_proofs_are_checked()
+    {
+        let clauses = cnf(&["A B", "~A B", "~B"]);
+        let step = |left, right, pivot, resolvent : &[&str]| ResolutionStep { left: left, right: right, pivot: pivot, resolvent: resolvent.iter().map(|&l| lit(l)).collect() };
+        let proof = ResolutionProof { steps: vec!(step(0, 1, 'A', &["B"]), step(3, 2, 'B', &[])) };
+        assert_eq!(check_resolution_proof(&clauses, &proof), Ok(()));
+
+        let mut wrong = proof.clone();
+        wrong.steps[0].resolvent = vec!(lit("~B"));
+        assert_eq!(check_resolution_proof(&clauses, &wrong), Err(ProofError::WrongResolvent { step: 0, expected: vec!(lit("B")) }));
+        let mut wrong = proof.clone();
+        wrong.steps[1].right = 7;
+        assert_eq!(check_resolution_proof(&clauses, &wrong), Err(ProofError::UnknownClause { step: 1, clause: 7 }));
+        let mut wrong = proof.clone();
+        wrong.steps[0].pivot = 'B';
+        assert_eq!(check_resolution_proof(&clauses, &wrong), Err(ProofError::BadPivot { step: 0 }));
+        let short = ResolutionProof { steps: vec!(proof.steps[0].clone()) };
+        assert_eq!(check_resolution_proof(&clauses, &short), Err(ProofError::NoEmptyClause));
+    }
+
+    #[test]
+    fn equivalent_formulas_are_grouped()
+    {
+        let groups = group_equivalent(vec!(p("A OR B"), p("B OR A"), p("A AND B"), p("~(~A AND ~B)"), p("C")));
+        assert_eq!(groups, vec!(vec!(0, 1, 3), vec!(2), vec!(4)));
+    }
+
+    #[test]
+    fn dnf_term_size_estimate_is_exact()
+    {
+        let mut rng = Rng(12345);
+        for _ in 0..100 {
+            let f = rng.formula(4, 3);
+            // The terms of f are the negated clauses of ~f.
+            let widest = nnf_clauses(&to_nnf(Formula::Not(box f.clone()))).iter().map(|c| c.len()).max().unwrap_or(0);
+            assert_eq!(dnf_max_term_size_estimate(&f), widest, "{}", f);
+        }
+    }
+
+    #[test]
+    fn first_uip_learns_shorter_clauses()
+    {
+        let clauses = cnf(&["~X Y", "~A ~Y B", "~B C", "~B D", "~C ~D ~X"]);
+        let mut learned = Vec::new();
+        for &scheme in &[UipScheme::FirstUip, UipScheme::DecisionUip] {
+            let mut solver = Solver::with_config(SolverConfig { uip_scheme: scheme, ..SolverConfig::default() });
+            for c in &clauses {
+                solver.add_clause(c);
+            }
+            assert!(solver.decide(lit("X")));
+            assert!(!solver.decide(lit("A")));
+            let conflict = solver.clauses.iter().position(|c| c.lits.iter().all(|&l| solver.lit_value(l) == Some(false))).unwrap();
+            let (clause, _) = solver.analyze(conflict);
+            learned.push(sorted(clause.iter().map(|&l| solver.literal(l)).collect()));
+        }
+        assert_eq!(learned[0], vec!(lit("~B"), lit("~X")));
+        assert_eq!(learned[1], vec!(lit("~A"), lit("~X"), lit("~Y")));
+
+        let mut rng = Rng(424242);
+        for _ in 0..40 {
+            let clauses = rng.clauses(8, 36, 3);
+            let expected = brute_sat(&clauses);
+            for &scheme in &[UipScheme::FirstUip, UipScheme::DecisionUip] {
+                let mut solver = Solver::with_config(SolverConfig { uip_scheme: scheme, ..SolverConfig::default() });
+                for c in &clauses {
+                    solver.add_clause(c);
+                }
+                assert_eq!(solver.solve() != SatResult::Unsat, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn smtlib_script_declares_and_asserts()
+    {
+        let script = to_smtlib_script(&p("(A -> B) AND ~C"));
+        assert_eq!(script.matches("(declare-fun ").count(), 3);
+        assert!(script.contains("(declare-fun A () Bool)\n(declare-fun B () Bool)\n(declare-fun C () Bool)\n"));
+        assert_eq!(script.matches("(assert ").count(), 1);
+        assert!(script.contains("(assert (and (=> A B) (not C)))\n(check-sat)\n(get-model)\n"));
+    }
+
+    #[test]
+    fn digit_atoms_get_identifier_names()
+    {
+        let f = Formula::And(vec!(Formula::Atom('1'), Formula::Atom('d'), Formula::Atom('\u{e9}')));
+        let names : Vec<String> = atoms(&f).into_iter().map(ascii_name).collect();
+        assert_eq!(names, vec!("d1", "d", "xe9"));
+        assert!(to_smtlib_script(&f).contains("(declare-fun d1 () Bool)\n"));
+        assert!(to_netlist(&f).to_verilog().contains("input d1"));
+    }
+
+    #[test]
+    fn u64_algebra_evaluates_64_rows()
+    {
+        let f = p("(A -> ~B) <-> IFF(C, D OR E, ~F)");
+        let atoms = atoms(&f);
+        let words : HashMap<char, u64> = atoms.iter().map(|&c| {
+            (c, (0..64).fold(0u64, |w, row| w | (assignment(&atoms, row)[&c] as u64) << row))
+        }).collect();
+        let column = eval_in(&f, &words);
+        for row in 0..64 {
+            assert_eq!((column >> row) & 1 == 1, eval(&f, &assignment(&atoms, row)));
+        }
+        let values : HashMap<char, bool> = assignment(&atoms, 5);
+        assert_eq!(eval_in(&f, &values), eval(&f, &values));
+    }
+
+    #[test]
+    fn bit_column_matches_the_scalar_table()
+    {
+        for s in &["A", "A AND B AND C AND D AND E AND F AND G", "IFF(A, B, C, D, E, F, G, H) -> I"] {
+            let f = p(s);
+            let atoms = atoms(&f);
+            let bits = truth_table_bits(&f);
+            let scalar : Vec<bool> = (0..(1u64 << atoms.len())).map(|row| eval(&f, &assignment(&atoms, row))).collect();
+            assert_eq!((0..bits.len()).map(|i| bits.get(i)).collect::<Vec<bool>>(), scalar);
+            assert_eq!(bits.count_ones(), scalar.iter().filter(|&&b| b).count());
+        }
+    }
+
+    #[test]
+    fn shortest_equivalent_of_a_redundant_formula()
+    {
+        let f = p("(A AND B) OR (A AND ~B) OR (A AND C)");
+        let g = shortest_equivalent(&f, size(&f)).unwrap();
+        assert!(size(&g) < size(&f));
+        assert_eq!(g, p("A"));
+        assert_eq!(shortest_equivalent(&p("(A OR B) AND ~(A AND B)"), 3), None);
+    }
+
+    #[test]
+    fn entropy_of_constant_and_balanced()
+    {
+        assert_eq!(output_entropy(&p("A OR ~A")), 0.0);
+        assert_eq!(output_entropy(&p("A AND ~A")), 0.0);
+        assert_eq!(output_entropy(&p("~(A <-> B)")), 1.0);
+        assert!((output_entropy(&p("A AND B")) - 0.811278).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sampled_fraction_of_satisfying_assignments()
+    {
+        assert_eq!(estimate_sat_fraction(&p("A OR ~A"), 100, 1), 1.0);
+        assert_eq!(estimate_sat_fraction(&p("A AND ~A"), 100, 1), 0.0);
+        assert!((estimate_sat_fraction(&p("P"), 10000, 2) - 0.5).abs() < 0.03);
+        let f = p("(A OR B) AND (C OR ~D)");
+        assert!((estimate_sat_fraction(&f, 10000, 3) - count_models(&f) as f64 / 16.0).abs() < 0.03);
+        assert_eq!(estimate_sat_fraction(&f, 500, 4), estimate_sat_fraction(&f, 500, 4));
+    }
+
+    #[test]
+    fn symmetric_atom_groups()
+    {
+        assert_eq!(symmetric_groups(&p("A OR B OR C")), vec!(vec!('A', 'B', 'C')));
+        assert!(symmetric_groups(&p("A -> B")).is_empty());
+        assert_eq!(symmetric_groups(&p("(A AND B) OR C")), vec!(vec!('A', 'B')));
+    }
+
+    #[test]
+    fn symmetry_breaking_keeps_satisfiability()
+    {
+        for s in &["A OR B OR C", "(A OR B) AND ~(A AND B)", "A AND B AND ~A"] {
+            let f = p(s);
+            let broken = Formula::And(vec!(f.clone(), cnf_formula(&symmetry_breaking_clauses(&f))));
+            if count_models(&f) > 0 {
+                assert!(count_models(&broken) > 0, "{}", s);
+            } else {
+                assert_eq!(count_models(&broken), 0);
+            }
+        }
+        let f = p("A OR B OR C");
+        assert!(count_models(&Formula::And(vec!(f.clone(), cnf_formula(&symmetry_breaking_clauses(&f))))) < count_models(&f));
+    }
+
+    #[test]
+    fn decide_certificates_check_out()
+    {
+        for s in &["(A OR B) AND (~A OR C) AND ~C", "(A <-> B) AND (B <-> C) AND ~(A <-> C)", "A AND ~A", "P"] {
+            let f = p(s);
+            match decide(&f) {
+                Decision::Sat(m) => {
+                    assert_eq!(check_model(&f, &m), Ok(()));
+                    assert_eq!(atoms(&f), sorted(m.keys().cloned().collect()));
+                }
+                Decision::Unsat(proof) => {
+                    assert_eq!(check_resolution_proof(&to_cnf_tseitin(&f), &proof), Ok(()));
+                    assert_eq!(count_models(&f), 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decide_keeps_private_use_atoms_of_the_formula()
+    {
+        let private = std::char::from_u32(0xE000).unwrap();
+        let f = Formula::And(vec!(Formula::Atom(private), p("A OR (B AND C)")));
+        match decide(&f) {
+            Decision::Sat(m) => {
+                assert_eq!(sorted(m.keys().cloned().collect()), atoms(&f));
+                assert_eq!(check_model(&f, &m), Ok(()));
+            }
+            Decision::Unsat(_) => panic!("satisfiable"),
+        }
+    }
+
+    #[test]
+    fn boolean_derivatives()
+    {
+        assert!(equivalent(&boolean_derivative(&p("A AND B"), 'A'), &p("B")));
+        assert_eq!(boolean_derivative(&p("B OR C"), 'A'), Formula::False);
+        assert_eq!(boolean_derivative(&p("A AND ~A AND B"), 'A'), Formula::False);
+        assert_eq!(boolean_derivative(&p("(A AND (B OR C) AND (B OR ~C)) OR (~A AND B)"), 'A'), Formula::False);
+        assert!(equivalent(&boolean_derivative(&p("~(A <-> B)"), 'A'), &Formula::True));
+    }
+
+    #[test]
+    fn stuck_at_tests_of_and()
+    {
+        let (stuck_at_0, stuck_at_1) = stuck_at_tests(&p("A AND B"), 'A');
+        assert_eq!(stuck_at_0, vec!(env(&[('A', true), ('B', true)])));
+        assert_eq!(stuck_at_1, vec!(env(&[('A', false), ('B', true)])));
+    }
+
+    #[test]
+    fn read_once_forms()
+    {
+        let g = to_read_once(&p("(A AND B) OR (A AND C)")).unwrap();
+        assert_eq!(g, p("A AND (B OR C)"));
+        assert_eq!(to_read_once(&p("(A OR B) AND ~(A AND B)")), None);
+        assert_eq!(to_read_once(&p("A -> B")), Some(p("~A OR B")));
+    }
 }